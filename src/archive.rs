@@ -0,0 +1,123 @@
+// With TRYBUILD_ARCHIVE=record, the raw output of building each test case,
+// from before any normalization, is kept in the wip directory. A later run
+// with TRYBUILD_ARCHIVE=replay takes the output from there instead of from
+// cargo, and normalizes and compares it as usual. That makes it quick to try
+// out a change to the normalization rules against a whole suite, or to
+// rewrite every *.stderr file after one, and lets the comparison be debugged
+// without a compiler at hand.
+//
+//     wip/archive/tests/ui/foo.rs.json
+//     wip/archive/nightly/tests/ui/foo.rs.json   with several toolchains
+//
+// Only the build is recorded, so test cases that should pass are ignored when
+// replaying.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+impl Mode {
+    pub fn env() -> Result<Option<Self>> {
+        let var = match env::var_os("TRYBUILD_ARCHIVE") {
+            Some(var) => var,
+            None => return Ok(None),
+        };
+
+        match var.as_os_str().to_str() {
+            Some("record") => Ok(Some(Mode::Record)),
+            Some("replay") => Ok(Some(Mode::Replay)),
+            _ => Err(Error::Var("TRYBUILD_ARCHIVE", var)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Recording {
+    // Bin name of the test case in the generated project.
+    pub name: String,
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+    // Rustc's JSON diagnostics, with json_snapshots.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Value>,
+}
+
+// Recordings of a run are kept in `dir` under the path of each test case
+// relative to `crate_dir`.
+pub fn save(dir: &Path, crate_dir: &Path, test: &Path, recording: &Recording) -> io::Result<()> {
+    let path = path(dir, crate_dir, test)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string_pretty(recording)?;
+    fs::write(path, json + "\n")
+}
+
+pub fn load(dir: &Path, crate_dir: &Path, test: &Path) -> Result<Recording> {
+    let path = path(dir, crate_dir, test).map_err(|err| Error::Open(test.to_owned(), err))?;
+    let json = fs::read(&path).map_err(|err| Error::Open(path.clone(), err))?;
+    serde_json::from_slice(&json).map_err(|err| Error::Open(path, err.into()))
+}
+
+// A test case outside of the crate directory has no place in the archive:
+// joining its path as is would put the recording next to the test case, or
+// anywhere at all by way of `..`.
+fn path(dir: &Path, crate_dir: &Path, test: &Path) -> io::Result<PathBuf> {
+    let relative = test.strip_prefix(crate_dir).unwrap_or(test);
+    let mut path = dir.to_owned();
+    let mut file_name = None;
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => {
+                path.extend(file_name.take());
+                file_name = Some(name.to_owned());
+            }
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                file_name = None;
+                break;
+            }
+        }
+    }
+    match file_name {
+        Some(mut file_name) => {
+            file_name.push(".json");
+            path.push(file_name);
+            Ok(path)
+        }
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only test cases inside of the crate directory can be archived",
+        )),
+    }
+}
+
+#[test]
+fn test_path() {
+    let dir = Path::new("/krate/wip/archive");
+    let crate_dir = Path::new("/krate");
+    let path = |test: &str| path(dir, crate_dir, Path::new(test)).ok();
+    assert_eq!(
+        path("tests/ui/foo.rs"),
+        Some(dir.join("tests/ui/foo.rs.json")),
+    );
+    assert_eq!(
+        path("/krate/tests/ui/foo.rs"),
+        Some(dir.join("tests/ui/foo.rs.json")),
+    );
+    assert_eq!(path("./tests/foo.rs"), Some(dir.join("tests/foo.rs.json")));
+    assert_eq!(path("/elsewhere/foo.rs"), None);
+    assert_eq!(path("../foo.rs"), None);
+    assert_eq!(path("tests/../../foo.rs"), None);
+}
@@ -1,13 +1,22 @@
-use serde::Deserialize;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::SystemTime;
 
+use crate::dependencies;
 use crate::error::{Error, Result};
 use crate::manifest::Name;
+use crate::normalize;
 use crate::run::Project;
 use crate::rustflags;
+use crate::term;
+use crate::timings;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Metadata {
     pub target_directory: PathBuf,
     pub workspace_root: PathBuf,
@@ -18,28 +27,84 @@ fn raw_cargo() -> Command {
 }
 
 fn cargo(project: &Project) -> Command {
-    let mut cmd = raw_cargo();
+    let mut cmd = match &project.toolchain {
+        Some(toolchain) => {
+            // Go through the rustup proxy rather than $CARGO, which points
+            // directly at the cargo binary of the toolchain running the tests.
+            let mut cmd = Command::new("cargo");
+            cmd.arg(format!("+{}", toolchain));
+            cmd.env_remove("RUSTUP_TOOLCHAIN");
+            cmd
+        }
+        None => raw_cargo(),
+    };
     cmd.current_dir(&project.dir);
-    cmd.env(
-        "CARGO_TARGET_DIR",
-        path!(project.target_dir / "tests" / "target"),
-    );
-    cmd.arg("--offline");
+    cmd.env("CARGO_TARGET_DIR", &project.cargo_target_dir);
+    if let Some(wrapper) = &project.rustc_wrapper {
+        cmd.env("RUSTC_WRAPPER", wrapper);
+    }
+    if let Some(rustc) = &project.rustc {
+        cmd.env("RUSTC", rustc);
+        cmd.env_remove("RUSTUP_TOOLCHAIN");
+    }
+    if project.offline {
+        cmd.arg("--offline");
+    }
+    if project.bindeps {
+        cmd.arg("-Zbindeps");
+    }
     rustflags::set_env(&mut cmd);
+    rustflags::set_profile_file(&mut cmd);
     cmd
 }
 
 pub fn build_dependencies(project: &Project) -> Result<()> {
-    let _ = cargo(project).arg("generate-lockfile").status();
+    let _span = span!("build_dependencies", target_dir = %project.cargo_target_dir.display());
 
-    let status = cargo(project)
+    // Regenerating would discard the versions locked by the workspace. Cargo
+    // adds whatever is missing from a shared lockfile during the build.
+    if !project.shared_lockfile {
+        let _ = cargo(project).arg("generate-lockfile").status();
+        pin_git_dependencies(project);
+    }
+
+    let start = SystemTime::now();
+    let mut child = cargo(project)
         .arg(if project.has_pass { "build" } else { "check" })
         .arg("--bin")
         .arg(&project.name)
+        .args(target(project))
+        .args(profile(project))
         .args(features(project))
-        .status()
+        .args(&project.cargo_args)
+        .args(timings(project))
+        .args(jobs(project))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
         .map_err(Error::Cargo)?;
 
+    // Read on the side so that a full pipe cannot hold Cargo up while stderr
+    // is being forwarded.
+    let mut stdout = child.stdout.take().unwrap();
+    let stdout = thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = stdout.read_to_end(&mut bytes);
+        bytes
+    });
+
+    // Errors about the manifest, such as a test case path that cannot be
+    // read, refer to the generated bin names. Point out the test case
+    // instead. The result goes to stderr like it would have from Cargo.
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    for line in stderr.lines() {
+        let line = normalize::bin_names(&line?, &project.bin_paths);
+        let _ = writeln!(term::lock(), "{}", line);
+    }
+
+    let status = child.wait().map_err(Error::Cargo)?;
+    let stdout = stdout.join().unwrap_or_default();
+    timings::collect(project, &stdout, start);
     if status.success() {
         Ok(())
     } else {
@@ -47,33 +112,297 @@ pub fn build_dependencies(project: &Project) -> Result<()> {
     }
 }
 
-pub fn build_test(project: &Project, name: &Name) -> Result<Output> {
-    let _ = cargo(project)
-        .arg("clean")
+// A freshly generated lockfile has every git dependency that is specified by
+// branch or tag at its latest commit. Go back to the commit that the
+// workspace is locked to, which Cargo has already fetched.
+fn pin_git_dependencies(project: &Project) {
+    let locked = dependencies::get_lockfile(&project.workspace);
+    let generated = dependencies::get_lockfile(&project.dir);
+
+    for package in &generated.package {
+        let (source, commit) = match package.git_commit() {
+            Some(git) => git,
+            None => continue,
+        };
+        let pinned = locked
+            .package
+            .iter()
+            .find_map(|locked| match locked.git_commit() {
+                Some((locked_source, locked_commit))
+                    if locked.name == package.name && locked_source == source =>
+                {
+                    Some(locked_commit)
+                }
+                _ => None,
+            });
+        if let Some(pinned) = pinned {
+            if pinned != commit {
+                let _ = cargo(project)
+                    .arg("update")
+                    .arg("--package")
+                    .arg(&package.name)
+                    .arg("--precise")
+                    .arg(pinned)
+                    .status();
+            }
+        }
+    }
+}
+
+pub struct BuildOutput {
+    pub output: Output,
+    // Cargo's own progress and status lines, if not suppressed by --quiet.
+    pub status_lines: String,
+    // Rustc's JSON diagnostics for the test case, with json_snapshots.
+    pub diagnostics: Vec<Value>,
+}
+
+pub fn build_test(project: &Project, name: &Name, quiet: bool) -> Result<BuildOutput> {
+    // No `cargo clean` beforehand: test cases that fail to compile are never
+    // fresh, and for ones that compile Cargo replays the cached diagnostics.
+    // Keeping the incremental state of every bin makes large suites fast.
+    let mut cmd = cargo(project);
+    cmd.arg(if project.has_pass { "build" } else { "check" })
+        .arg("--bin")
+        .arg(name)
+        .args(features(project));
+    build(project, cmd, Some(name), quiet)
+}
+
+// A test case that is a crate of its own is built in place, with its own
+// Cargo.toml and features, into the same target directory as the generated
+// project.
+pub fn build_crate(project: &Project, dir: &Path, quiet: bool) -> Result<BuildOutput> {
+    let mut cmd = cargo(project);
+    cmd.current_dir(dir).arg("build");
+    build(project, cmd, None, quiet)
+}
+
+// A test case that is a proc-macro crate is the library of a package of its
+// own, named like the bin it would otherwise be, in the generated workspace.
+pub fn build_proc_macro(project: &Project, name: &Name, quiet: bool) -> Result<BuildOutput> {
+    let mut cmd = cargo(project);
+    cmd.arg(if project.has_pass { "build" } else { "check" })
         .arg("--package")
-        .arg(&project.name)
-        .arg("--color=never")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status();
+        .arg(name)
+        .arg("--lib");
+    build(project, cmd, Some(name), quiet)
+}
 
+fn build(
+    project: &Project,
+    mut cmd: Command,
+    name: Option<&Name>,
+    quiet: bool,
+) -> Result<BuildOutput> {
+    cmd.args(target(project))
+        .args(profile(project))
+        .args(jobs(project))
+        .args(&project.cargo_args)
+        .args(timings(project))
+        .arg(if project.ansi_snapshots {
+            "--color=always"
+        } else {
+            "--color=never"
+        });
+    if quiet {
+        cmd.arg("--quiet");
+    }
+    // Even for human-readable output, so that warnings from dependencies can
+    // be told apart from those of the test case.
+    cmd.arg(if project.ansi_snapshots {
+        "--message-format=json-diagnostic-rendered-ansi"
+    } else {
+        "--message-format=json"
+    });
+
+    let start = SystemTime::now();
+    let mut output = cmd.output().map_err(Error::Cargo)?;
+    timings::collect(project, &output.stdout, start);
+    let status_lines = if quiet {
+        String::new()
+    } else {
+        take_status_lines(&mut output)
+    };
+    let json = project.json_diagnostics || project.json_snapshots;
+    let diagnostics = extract_rendered(&mut output, name, !json);
+    let diagnostics = if json { diagnostics } else { Vec::new() };
+    Ok(BuildOutput {
+        output,
+        status_lines,
+        diagnostics,
+    })
+}
+
+// Cargo prints its status with the verb right-aligned in 12 columns:
+//
+//    Compiling trybuild-tests v0.0.0 (/path/to/target/tests/trybuild)
+//     Finished `dev` profile [unoptimized + debuginfo] target(s) in 0.31s
+fn take_status_lines(output: &mut Output) -> String {
+    fn is_status_line(line: &str) -> bool {
+        line.len() > 12
+            && line.as_bytes()[12] == b' '
+            && line[..12]
+                .trim_start()
+                .bytes()
+                .all(|b| b.is_ascii_alphabetic())
+            && line[..12]
+                .trim_start()
+                .starts_with(|ch: char| ch.is_ascii_uppercase())
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    let mut diagnostics = String::new();
+    let mut status_lines = String::new();
+    for line in stderr.lines() {
+        let dest = if is_status_line(&normalize::strip_ansi(line)) {
+            &mut status_lines
+        } else {
+            &mut diagnostics
+        };
+        *dest += line;
+        dest.push('\n');
+    }
+    output.stderr = diagnostics.into_bytes();
+    status_lines
+}
+
+#[derive(Deserialize)]
+struct JsonMessage {
+    reason: String,
+    target: Option<Target>,
+    message: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+}
+
+// Replace Cargo's stderr by the diagnostics rendered by rustc for the test
+// case's bin, and keep from stdout only what is not a JSON message. Warnings
+// from building dependencies, including the crate under test, are dropped;
+// for a test case that is a crate of its own, nothing is. Returns the same
+// diagnostics in structured form.
+//
+// With `keep_cargo_output`, Cargo's own messages stay and the diagnostics go
+// where Cargo would have printed them in human-readable mode, before it says
+// that the build failed.
+fn extract_rendered(
+    output: &mut Output,
+    name: Option<&Name>,
+    keep_cargo_output: bool,
+) -> Vec<Value> {
+    let mut stdout = Vec::new();
+    let mut rendered = String::new();
+    let mut diagnostics = Vec::new();
+
+    for line in output.stdout.split(|&b| b == b'\n') {
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_slice::<JsonMessage>(line) {
+            Ok(json) => {
+                if json.reason != "compiler-message" {
+                    continue;
+                }
+                let other_target = match (&json.target, name) {
+                    (Some(target), Some(name)) => target.name != name.0,
+                    _ => false,
+                };
+                if other_target {
+                    continue;
+                }
+                if let Some(message) = json.message {
+                    if let Some(text) = message["rendered"].as_str() {
+                        rendered += text;
+                    }
+                    diagnostics.push(message);
+                }
+            }
+            Err(_) => {
+                stdout.extend_from_slice(line);
+                stdout.push(b'\n');
+            }
+        }
+    }
+
+    output.stdout = stdout;
+    // If no diagnostics were emitted, whatever went wrong was reported by
+    // Cargo itself.
+    if !rendered.is_empty() {
+        output.stderr = if keep_cargo_output {
+            splice_rendered(&String::from_utf8_lossy(&output.stderr), &rendered).into_bytes()
+        } else {
+            rendered.into_bytes()
+        };
+    }
+
+    diagnostics
+}
+
+fn splice_rendered(cargo_stderr: &str, rendered: &str) -> String {
+    let mut stderr = String::new();
+    let mut rendered = Some(rendered);
+    for line in cargo_stderr.lines() {
+        let plain = normalize::strip_ansi(line);
+        if plain.starts_with("error: could not compile `")
+            || plain.starts_with("error: Could not compile `")
+        {
+            stderr.extend(rendered.take());
+        }
+        stderr += line;
+        stderr.push('\n');
+    }
+    stderr.extend(rendered);
+    stderr
+}
+
+#[test]
+fn test_splice_rendered() {
+    let cargo_stderr = "\
+warning: unused manifest key: foo
+error: could not compile `foo` (bin \"bar\") due to 1 previous error
+";
+    let rendered = "error[E0308]: mismatched types\n";
+    assert_eq!(
+        splice_rendered(cargo_stderr, rendered),
+        "\
+warning: unused manifest key: foo
+error[E0308]: mismatched types
+error: could not compile `foo` (bin \"bar\") due to 1 previous error
+",
+    );
+    assert_eq!(splice_rendered("", rendered), rendered);
+}
+
+pub fn run_test(project: &Project, name: &Name) -> Result<Output> {
+    // A runner configured through CARGO_TARGET_<TRIPLE>_RUNNER is inherited
+    // from the environment, and one from target.<triple>.runner in the
+    // crate's Cargo config is copied into the generated project's config.
     cargo(project)
-        .arg(if project.has_pass { "build" } else { "check" })
+        .arg("run")
         .arg("--bin")
         .arg(name)
+        .args(target(project))
+        .args(profile(project))
         .args(features(project))
+        .args(jobs(project))
+        .args(&project.cargo_args)
         .arg("--quiet")
         .arg("--color=never")
         .output()
         .map_err(Error::Cargo)
 }
 
-pub fn run_test(project: &Project, name: &Name) -> Result<Output> {
+pub fn run_crate(project: &Project, dir: &Path) -> Result<Output> {
     cargo(project)
+        .current_dir(dir)
         .arg("run")
-        .arg("--bin")
-        .arg(name)
-        .args(features(project))
+        .args(target(project))
+        .args(profile(project))
+        .args(jobs(project))
+        .args(&project.cargo_args)
         .arg("--quiet")
         .arg("--color=never")
         .output()
@@ -81,11 +410,15 @@ pub fn run_test(project: &Project, name: &Name) -> Result<Output> {
 }
 
 pub fn metadata() -> Result<Metadata> {
-    let output = raw_cargo()
-        .arg("metadata")
-        .arg("--format-version=1")
-        .output()
-        .map_err(Error::Cargo)?;
+    let mut cmd = raw_cargo();
+    cmd.arg("metadata").arg("--format-version=1");
+    if let Some(manifest_dir) = env::var_os("CARGO_MANIFEST_DIR") {
+        let manifest = dependencies::get_manifest(Path::new(&manifest_dir));
+        if manifest.has_artifact_dependencies() {
+            cmd.arg("-Zbindeps");
+        }
+    }
+    let output = cmd.output().map_err(Error::Cargo)?;
 
     serde_json::from_slice(&output.stdout).map_err(|err| {
         print!("{}", String::from_utf8_lossy(&output.stderr));
@@ -93,7 +426,46 @@ pub fn metadata() -> Result<Metadata> {
     })
 }
 
+// Version of the compiler that builds the test cases, if it can be determined.
+// "rustc 1.80.0-nightly (032af18af 2024-06-02)"
+pub fn rustc_version(project: &Project) -> Option<String> {
+    let mut cmd = match (&project.rustc, &project.toolchain) {
+        (Some(rustc), _) => Command::new(rustc),
+        (None, Some(toolchain)) => {
+            let mut cmd = Command::new("rustc");
+            cmd.arg(format!("+{}", toolchain));
+            cmd.env_remove("RUSTUP_TOOLCHAIN");
+            cmd
+        }
+        (None, None) => Command::new(env::var_os("RUSTC").unwrap_or_else(|| "rustc".into())),
+    };
+    let output = cmd.arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+pub fn sysroot(rustc: &Path) -> Result<PathBuf> {
+    let output = Command::new(rustc)
+        .arg("--print")
+        .arg("sysroot")
+        .output()
+        .map_err(Error::Rustc)?;
+
+    if !output.status.success() {
+        print!("{}", String::from_utf8_lossy(&output.stderr));
+        return Err(Error::RustcFail);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(PathBuf::from(stdout.trim_end()))
+}
+
 fn features(project: &Project) -> Vec<String> {
+    if project.all_features {
+        return vec!["--all-features".to_owned()];
+    }
     match &project.features {
         Some(features) => vec![
             "--no-default-features".to_owned(),
@@ -103,3 +475,43 @@ fn features(project: &Project) -> Vec<String> {
         None => vec![],
     }
 }
+
+fn profile(project: &Project) -> Option<&'static str> {
+    if project.release {
+        Some("--release")
+    } else {
+        None
+    }
+}
+
+fn timings(project: &Project) -> &'static [&'static str] {
+    match &project.timings {
+        Some(timings) => timings.args(),
+        None => &[],
+    }
+}
+
+// Whether a nightly Cargo takes `--timings=json`. Only the ones from before it
+// was removed again list the formats in their help.
+pub fn supports_timings_json(project: &Project) -> bool {
+    let mut cmd = cargo(project);
+    cmd.current_dir(&project.source_dir);
+    match cmd.arg("build").arg("--help").output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).contains("--timings[="),
+        Err(_) => false,
+    }
+}
+
+fn jobs(project: &Project) -> Vec<String> {
+    match project.jobs {
+        Some(jobs) => vec!["--jobs".to_owned(), jobs.to_string()],
+        None => vec![],
+    }
+}
+
+fn target(project: &Project) -> Vec<String> {
+    match &project.target {
+        Some(target) => vec!["--target".to_owned(), target.clone()],
+        None => vec![],
+    }
+}
@@ -0,0 +1,481 @@
+// The cargo-trybuild binary, built with the "cli" feature. It manages the
+// snapshots of a crate's ui tests from the outside, so that accepting new
+// compiler output does not involve rerunning the tests with TRYBUILD=overwrite.
+//
+//     cargo trybuild list             snapshots waiting in wip, and orphans
+//     cargo trybuild review [TEST..]  accept, reject or edit them one at a time
+//     cargo trybuild update [TEST..]  accept them all
+//     cargo trybuild prune [--yes]    delete orphaned snapshots and stale wip files
+//     cargo trybuild watch [TEST..]   rerun affected tests whenever a file changes
+//
+// A snapshot is orphaned when the test case it belongs to no longer exists.
+// The test cases of doc_examples exist in the target directory instead, where
+// the last run of the tests extracted them.
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use termcolor::Color::{self, Green, Red, Yellow};
+
+use crate::cargo;
+use crate::directives;
+use crate::env::Update;
+use crate::examples;
+use crate::pending::{self, Pending};
+use crate::review;
+use crate::run::TARGET_OS;
+use crate::term;
+use crate::watch;
+
+const USAGE: &str = "\
+Manage the snapshots of trybuild ui tests
+
+Usage: cargo trybuild <COMMAND> [TEST...] [-- CARGO_TEST_ARGS...]
+       cargo trybuild prune [--yes]
+
+Commands:
+    list      Show snapshots waiting in the wip directory and orphaned snapshots
+    review    Accept, reject or edit each waiting snapshot, shown side by side
+    update    Accept every waiting snapshot
+    prune     Delete orphaned snapshots and stale files in the wip directory
+    watch     Rerun the tests affected by each change to the crate or its tests
+
+Without --yes, prune only lists the files that it would delete.
+
+Arguments after `--` are passed to `cargo test` by watch, for example
+`cargo trybuild watch -- --test ui` to run only the tests/ui.rs test binary.
+
+Snapshots of test cases whose path contains one of the given TEST strings are
+selected, or all of them if none are given. The wip directory is *wip* next to
+Cargo.toml unless TRYBUILD_WIP_DIR says otherwise.
+";
+
+pub(crate) struct Crate {
+    pub dir: PathBuf,
+    pub wip_dir: PathBuf,
+    // Where the tests extract the examples of doc_examples to.
+    examples_dir: Option<PathBuf>,
+}
+
+#[derive(PartialEq, Debug)]
+struct Args {
+    command: Command,
+    filters: Vec<String>,
+    // `prune --yes`
+    yes: bool,
+    // Everything after `--`, for `cargo test`.
+    cargo_args: Vec<String>,
+}
+
+#[derive(PartialEq, Debug)]
+enum Command {
+    List,
+    Review,
+    Update,
+    Prune,
+    Watch,
+    Help,
+}
+
+pub fn main() {
+    let mut args = env::args_os().skip(1).peekable();
+    // Cargo runs `cargo trybuild ...` as `cargo-trybuild trybuild ...`.
+    if args.peek().map_or(false, |arg| arg == "trybuild") {
+        args.next();
+    }
+    let Args {
+        command,
+        filters,
+        yes,
+        cargo_args,
+    } = match parse_args(args) {
+        Some(args) => args,
+        None => {
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = match command {
+        Command::Help => {
+            std::print!("{}", USAGE);
+            return;
+        }
+        command => Crate::locate().and_then(|krate| match command {
+            Command::List => list(&krate, &filters),
+            Command::Review => review::review(&krate, &filters),
+            Command::Update => update(&krate, &filters),
+            Command::Prune => prune(&krate, yes),
+            Command::Watch => watch::watch(&krate.dir, &filters, &cargo_args),
+            Command::Help => unreachable!(),
+        }),
+    };
+
+    if let Err(err) = result {
+        term::bold_color(Red);
+        print!("error");
+        term::reset();
+        println!(": {}", err);
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: impl Iterator<Item = OsString>) -> Option<Args> {
+    let mut args = args.map(|arg| arg.to_string_lossy().into_owned());
+    let command = match args.next()?.as_str() {
+        "list" => Command::List,
+        "review" => Command::Review,
+        "update" => Command::Update,
+        "prune" => Command::Prune,
+        "watch" => Command::Watch,
+        "help" | "-h" | "--help" => Command::Help,
+        _ => return None,
+    };
+    let mut filters: Vec<String> = args.by_ref().take_while(|arg| arg != "--").collect();
+    let cargo_args: Vec<String> = args.collect();
+    let yes = command == Command::Prune && filters == ["--yes"];
+    if yes {
+        filters.clear();
+    }
+    if command == Command::Prune && !filters.is_empty()
+        || command != Command::Watch && !cargo_args.is_empty()
+    {
+        return None;
+    }
+    Some(Args {
+        command,
+        filters,
+        yes,
+        cargo_args,
+    })
+}
+
+impl Crate {
+    fn locate() -> io::Result<Self> {
+        let cwd = env::current_dir()?;
+        let dir = match cwd.ancestors().find(|dir| dir.join("Cargo.toml").exists()) {
+            Some(dir) => dir.to_owned(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "could not find Cargo.toml in the current directory or any parent",
+                ));
+            }
+        };
+        let wip_dir = dir.join(env::var_os("TRYBUILD_WIP_DIR").unwrap_or_else(|| "wip".into()));
+        let examples_dir = examples_dir(&dir);
+        Ok(Crate {
+            dir,
+            wip_dir,
+            examples_dir,
+        })
+    }
+
+    // Whether `source` is an example that was extracted by the last run.
+    fn is_example(&self, source: &Path) -> bool {
+        match (&self.examples_dir, source.strip_prefix(&self.dir)) {
+            (Some(examples_dir), Ok(relative)) => examples_dir.join(relative).exists(),
+            _ => false,
+        }
+    }
+
+    // Entries whose snapshot is still there, in the order they were written.
+    pub fn pending(&self) -> Vec<Pending> {
+        let mut pending = pending::load(&self.wip_dir);
+        pending.retain(|p| self.wip_dir.join(&p.snapshot).exists());
+        pending
+    }
+}
+
+fn examples_dir(dir: &Path) -> Option<PathBuf> {
+    let manifest = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+    let manifest: toml::Value = toml::from_str(&manifest).ok()?;
+    let name = manifest.get("package")?.get("name")?.as_str()?;
+    let target_dir = cargo::metadata().ok()?.target_directory;
+    Some(examples::scratch_dir(&target_dir, name))
+}
+
+pub(crate) fn selected(entry: &Pending, filters: &[String]) -> bool {
+    selected_path(&entry.destination, filters)
+}
+
+fn list(krate: &Crate, filters: &[String]) -> io::Result<()> {
+    let pending = krate.pending();
+    let orphans = orphans(krate, &krate.dir.join("tests"))?;
+    let mut any = false;
+
+    for entry in pending.iter().filter(|p| selected(p, filters)) {
+        let (status, color) = if krate.dir.join(&entry.destination).exists() {
+            ("changed", Yellow)
+        } else {
+            ("new", Green)
+        };
+        status_line(status, color, &entry.destination);
+        any = true;
+    }
+    for orphan in &orphans {
+        let orphan = orphan.strip_prefix(&krate.dir).unwrap_or(orphan);
+        if selected_path(orphan, filters) {
+            status_line("orphan", Red, orphan);
+            any = true;
+        }
+    }
+
+    if !any {
+        println!("no pending or orphaned snapshots");
+    }
+    Ok(())
+}
+
+fn update(krate: &Crate, filters: &[String]) -> io::Result<()> {
+    let pending = krate.pending();
+    let mut remaining = Vec::new();
+    let mut accepted = 0;
+
+    for entry in pending {
+        if !selected(&entry, filters) {
+            remaining.push(entry);
+            continue;
+        }
+        // Same as TRYBUILD=overwrite, which leaves locked test cases alone.
+        let source = krate.dir.join(test_source(&entry.destination));
+        if directives::parse(&source).update == Some(Update::Check) {
+            status_line("locked", Yellow, &entry.destination);
+            remaining.push(entry);
+            continue;
+        }
+        accept(krate, &entry)?;
+        status_line("updated", Green, &entry.destination);
+        accepted += 1;
+    }
+
+    if accepted == 0 {
+        println!("no pending snapshots to accept");
+    }
+    pending::save(&krate.wip_dir, &remaining)
+}
+
+// Deletes nothing unless `yes`, only lists what would go.
+fn prune(krate: &Crate, yes: bool) -> io::Result<()> {
+    let mut stale = orphans(krate, &krate.dir.join("tests"))?;
+
+    // Snapshots of test cases that were deleted since, and files in the wip
+    // directory that nothing refers to.
+    let mut pending = krate.pending();
+    pending.retain(|p| has_test_case(krate, &krate.dir.join(&p.destination)));
+    if let Ok(entries) = fs::read_dir(&krate.wip_dir) {
+        let mut unreferenced = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let reserved = file_name == pending::INDEX || file_name == ".gitignore";
+            let referenced = pending.iter().any(|p| p.snapshot == file_name)
+                || file_name == "mismatches.diff" && !pending.is_empty();
+            if !reserved && !referenced && entry.file_type()?.is_file() {
+                unreferenced.push(entry.path());
+            }
+        }
+        unreferenced.sort();
+        stale.extend(unreferenced);
+        if yes {
+            pending::save(&krate.wip_dir, &pending)?;
+        }
+    }
+
+    let status = if yes { "removed" } else { "stale" };
+    for path in &stale {
+        if yes {
+            fs::remove_file(path)?;
+        }
+        status_line(status, Red, path.strip_prefix(&krate.dir).unwrap_or(path));
+    }
+    if stale.is_empty() {
+        println!("nothing to prune");
+    } else if !yes {
+        println!();
+        println!("rerun as `cargo trybuild prune --yes` to delete these files");
+    }
+    Ok(())
+}
+
+pub(crate) fn accept(krate: &Crate, entry: &Pending) -> io::Result<()> {
+    let snapshot = krate.wip_dir.join(&entry.snapshot);
+    let destination = krate.dir.join(&entry.destination);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Not a rename, which fails if wip is on a different filesystem.
+    fs::copy(&snapshot, destination)?;
+    fs::remove_file(snapshot)
+}
+
+// Every *.stderr file under `dir` whose test case does not exist.
+fn orphans(krate: &Crate, dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut orphans = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(orphans),
+        Err(err) => return Err(err),
+    };
+    let mut entries = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            orphans.extend(self::orphans(krate, &path)?);
+        } else if path.extension().map_or(false, |ext| ext == "stderr")
+            && !has_test_case(krate, &path)
+        {
+            orphans.push(path);
+        }
+    }
+    Ok(orphans)
+}
+
+// tests/ui/foo.stderr, tests/ui/foo.alt1.stderr, tests/ui/foo.nightly.stderr
+// and tests/ui/foo.linux.alt1.stderr all belong to tests/ui/foo.rs. Only the
+// qualifiers that trybuild itself puts there are taken off, so that
+// tests/ui/v1.2.stderr still belongs to tests/ui/v1.2.rs, and a test case
+// that exists under the name with fewer taken off comes first.
+pub(crate) fn test_source(snapshot: &Path) -> PathBuf {
+    let stems = stems(snapshot);
+    let sources: Vec<PathBuf> = stems
+        .iter()
+        .map(|stem| snapshot.with_file_name(format!("{}.rs", stem)))
+        .collect();
+    match sources.iter().find(|source| is_test_case(source)) {
+        Some(source) => source.clone(),
+        None => sources[0].clone(),
+    }
+}
+
+// The file name without its extension and `.altN`, followed by that with
+// one more qualifier taken off the end for each one that it could end with.
+fn stems(snapshot: &Path) -> Vec<String> {
+    let file_name = snapshot
+        .file_name()
+        .map_or_else(Default::default, |name| name.to_string_lossy().into_owned());
+    let mut stem = match file_name.rfind('.') {
+        Some(dot) => &file_name[..dot],
+        None => &file_name[..],
+    };
+    if let Some(dot) = stem.rfind('.') {
+        let alt = &stem[dot + 1..];
+        if alt.starts_with("alt") && alt.len() > 3 && alt[3..].bytes().all(|b| b.is_ascii_digit()) {
+            stem = &stem[..dot];
+        }
+    }
+
+    let mut stems = vec![stem.to_owned()];
+    for (dot, _) in stem.rmatch_indices('.') {
+        if dot > 0 && is_qualifier(&stem[dot + 1..]) {
+            stems.push(stem[..dot].to_owned());
+        }
+    }
+    stems
+}
+
+// A toolchain channel such as nightly or 1.70.0, an OS, or a feature set such
+// as serde+derive.
+fn is_qualifier(qualifier: &str) -> bool {
+    let is_channel = qualifier == "stable"
+        || qualifier == "beta"
+        || qualifier == "nightly"
+        || !qualifier.is_empty() && qualifier.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+    let is_os = qualifier == "unknown" || TARGET_OS.iter().any(|(_component, os)| *os == qualifier);
+    let is_feature_set = !qualifier.is_empty()
+        && qualifier.split('+').all(|feature| {
+            !feature.is_empty()
+                && feature
+                    .chars()
+                    .all(|ch| ch.is_alphanumeric() || ch == '_' || ch == '-')
+        });
+    is_channel || is_os || is_feature_set
+}
+
+// Either tests/ui/foo.rs or a crate in tests/ui/foo/.
+fn is_test_case(source: &Path) -> bool {
+    source.exists() || source.with_extension("").join("Cargo.toml").exists()
+}
+
+fn has_test_case(krate: &Crate, snapshot: &Path) -> bool {
+    let source = test_source(snapshot);
+    is_test_case(&source) || krate.is_example(&source)
+}
+
+fn selected_path(path: &Path, filters: &[String]) -> bool {
+    let path = path.to_string_lossy();
+    filters.is_empty() || filters.iter().any(|f| path.contains(f.as_str()))
+}
+
+fn status_line(status: &str, color: Color, path: &Path) {
+    term::bold_color(color);
+    print!("{:>9}", status);
+    term::reset();
+    println!(" {}", path.display());
+}
+
+#[test]
+fn test_stems() {
+    let cases: &[(&str, &[&str])] = &[
+        ("tests/ui/foo.stderr", &["foo"]),
+        ("tests/ui/foo.alt1.stderr", &["foo"]),
+        ("tests/ui/foo.nightly.stderr", &["foo.nightly", "foo"]),
+        ("tests/ui/foo.linux.alt2.stderr", &["foo.linux", "foo"]),
+        (
+            "tests/ui/foo.1.70.0.stderr",
+            &["foo.1.70.0", "foo.1.70", "foo.1", "foo"],
+        ),
+        (
+            "tests/ui/foo.serde+derive.stderr",
+            &["foo.serde+derive", "foo"],
+        ),
+        ("tests/ui/v1.2.stderr", &["v1.2", "v1"]),
+        ("tests/ui/foo bar.x y.stderr", &["foo bar.x y"]),
+    ];
+    for &(snapshot, stems) in cases {
+        assert_eq!(self::stems(Path::new(snapshot)), stems, "{}", snapshot);
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let args = |args: &[&str]| parse_args(args.iter().map(OsString::from));
+    let strings = |strings: &[&str]| strings.iter().map(|s| s.to_string()).collect();
+    assert_eq!(
+        args(&["list"]),
+        Some(Args {
+            command: Command::List,
+            filters: Vec::new(),
+            yes: false,
+            cargo_args: Vec::new(),
+        }),
+    );
+    assert_eq!(
+        args(&["watch", "foo", "--", "--test", "ui"]),
+        Some(Args {
+            command: Command::Watch,
+            filters: strings(&["foo"]),
+            yes: false,
+            cargo_args: strings(&["--test", "ui"]),
+        }),
+    );
+    assert_eq!(
+        args(&["prune", "--yes"]),
+        Some(Args {
+            command: Command::Prune,
+            filters: Vec::new(),
+            yes: true,
+            cargo_args: Vec::new(),
+        }),
+    );
+    assert_eq!(args(&["list", "--yes"]).map(|args| args.yes), Some(false));
+    assert_eq!(args(&["prune", "foo"]), None);
+    assert_eq!(args(&["update", "--", "--test", "ui"]), None);
+    assert_eq!(args(&["frobnicate"]), None);
+    assert_eq!(args(&[]), None);
+}
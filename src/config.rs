@@ -0,0 +1,263 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+pub use toml::value::Table;
+use toml::Value;
+
+// Cargo configuration that applies to the crate under test, gathered the way
+// Cargo does it: from .cargo/config.toml in the crate's directory and all of
+// its ancestors, then from $CARGO_HOME/config.toml. Files closer to the crate
+// take precedence.
+//
+// https://doc.rust-lang.org/cargo/reference/config.html#hierarchical-structure
+pub fn load(dir: &Path) -> Table {
+    let mut config = Table::new();
+
+    for ancestor in dir.ancestors() {
+        if let Some(table) = read(&ancestor.join(".cargo")) {
+            merge(&mut config, table);
+        }
+    }
+
+    if let Some(cargo_home) = cargo_home() {
+        if let Some(table) = read(&cargo_home) {
+            merge(&mut config, table);
+        }
+    }
+
+    config
+}
+
+// The part of that config which Cargo does not find by itself when it builds
+// the generated project in `project_dir`, because it is in a directory that is
+// not also an ancestor of the project. Cargo concatenates arrays such as
+// build.rustflags from every file it reads, so anything it can see must not be
+// passed along again.
+pub fn load_unseen(dir: &Path, project_dir: &Path) -> Table {
+    let mut config = Table::new();
+
+    for ancestor in dir.ancestors() {
+        if project_dir.starts_with(ancestor) {
+            break;
+        }
+        if let Some(table) = read(&ancestor.join(".cargo")) {
+            merge(&mut config, table);
+        }
+    }
+
+    config
+}
+
+fn read(dir: &Path) -> Option<Table> {
+    // If both exist, Cargo uses the file without the extension.
+    let content = fs::read_to_string(dir.join("config"))
+        .or_else(|_| fs::read_to_string(dir.join("config.toml")))
+        .ok()?;
+    let mut table: Table = toml::from_str(&content).ok()?;
+
+    // Relative paths in a config file are relative to the directory containing
+    // the .cargo directory. Make them absolute so that they keep pointing to
+    // the same place when copied into the generated project.
+    if let (Some(Value::Table(sources)), Some(base)) = (table.get_mut("source"), dir.parent()) {
+        for (_name, source) in sources.iter_mut() {
+            if let Value::Table(source) = source {
+                for key in &["directory", "local-registry"] {
+                    if let Some(Value::String(path)) = source.get_mut(*key) {
+                        *path = base.join(&*path).to_string_lossy().into_owned();
+                    }
+                }
+            }
+        }
+    }
+
+    // So are paths to executables that contain a slash, such as a runner.
+    if let (Some(Value::Table(targets)), Some(base)) = (table.get_mut("target"), dir.parent()) {
+        for (_name, target) in targets.iter_mut() {
+            if let Value::Table(target) = target {
+                match target.get_mut("runner") {
+                    Some(Value::String(runner)) => {
+                        let split = runner.find(' ').unwrap_or(runner.len());
+                        let program = executable(base, &runner[..split]);
+                        *runner = program + &runner[split..];
+                    }
+                    Some(Value::Array(runner)) => {
+                        if let Some(Value::String(program)) = runner.get_mut(0) {
+                            *program = executable(base, program);
+                        }
+                    }
+                    _ => {}
+                }
+                if let Some(Value::String(linker)) = target.get_mut("linker") {
+                    *linker = executable(base, linker);
+                }
+            }
+        }
+    }
+
+    // [env] values marked `relative = true` are relative to the same directory.
+    if let (Some(Value::Table(vars)), Some(base)) = (table.get_mut("env"), dir.parent()) {
+        for (_name, var) in vars.iter_mut() {
+            if let Value::Table(var) = var {
+                if var.get("relative").and_then(Value::as_bool) != Some(true) {
+                    continue;
+                }
+                if let Some(Value::String(path)) = var.get_mut("value") {
+                    *path = base.join(&*path).to_string_lossy().into_owned();
+                }
+                var.remove("relative");
+            }
+        }
+    }
+
+    Some(table)
+}
+
+fn executable(base: &Path, program: &str) -> String {
+    if program.contains('/') {
+        base.join(program).to_string_lossy().into_owned()
+    } else {
+        program.to_owned()
+    }
+}
+
+pub fn cargo_home() -> Option<PathBuf> {
+    if let Some(cargo_home) = env::var_os("CARGO_HOME") {
+        return Some(PathBuf::from(cargo_home));
+    }
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(path!(home / ".cargo"))
+}
+
+// Merge a less specific config file into the config accumulated so far.
+fn merge(config: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (config.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => merge(existing, table),
+            (Some(Value::Array(existing)), Value::Array(array)) => {
+                let mut array = array;
+                array.append(existing);
+                *existing = array;
+            }
+            (Some(_), _) => {}
+            (None, value) => {
+                config.insert(key, value);
+            }
+        }
+    }
+}
+
+pub fn get<'a>(config: &'a Table, path: &[&str]) -> Option<&'a Value> {
+    let (last, tables) = path.split_last()?;
+    let mut table = config;
+    for key in tables {
+        table = table.get(*key)?.as_table()?;
+    }
+    table.get(*last)
+}
+
+pub fn build_target(config: &Table) -> Option<String> {
+    if let Ok(target) = env::var("CARGO_BUILD_TARGET") {
+        return Some(target);
+    }
+    match get(config, &["build", "target"])? {
+        Value::String(target) => Some(target.clone()),
+        Value::Array(targets) if targets.len() == 1 => targets[0].as_str().map(str::to_owned),
+        _ => None,
+    }
+}
+
+pub fn build_rustflags(config: &Table) -> Vec<String> {
+    match get(config, &["build", "rustflags"]) {
+        Some(Value::String(flags)) => flags.split_whitespace().map(str::to_owned).collect(),
+        Some(Value::Array(flags)) => flags
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_owned)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Directories that dependency sources are replaced with, as in
+// `[source.vendored-sources] directory = "vendor"`.
+pub fn source_directories(config: &Table) -> Vec<PathBuf> {
+    let sources = match config.get("source") {
+        Some(Value::Table(sources)) => sources,
+        _ => return Vec::new(),
+    };
+    sources
+        .values()
+        .filter_map(|source| source.get("directory")?.as_str())
+        .map(PathBuf::from)
+        .collect()
+}
+
+// Sections of the config that affect where dependencies come from and how
+// they are downloaded, and the environment that `env!` sees at compile time.
+pub fn passthrough(config: &Table) -> Table {
+    let mut passthrough = Table::new();
+    for key in &["env", "http", "net", "registries", "registry", "source"] {
+        if let Some(value) = config.get(*key) {
+            passthrough.insert((*key).to_owned(), value.clone());
+        }
+    }
+
+    // Only how to link and run binaries for each target. Other settings such
+    // as target.<triple>.rustflags would replace trybuild's own.
+    if let Some(Value::Table(targets)) = config.get("target") {
+        let mut runners = Table::new();
+        for (name, target) in targets {
+            let mut settings = Table::new();
+            for key in &["linker", "runner"] {
+                if let Some(value) = target.get(*key) {
+                    settings.insert((*key).to_owned(), value.clone());
+                }
+            }
+            if !settings.is_empty() {
+                runners.insert(name.clone(), Value::Table(settings));
+            }
+        }
+        if !runners.is_empty() {
+            passthrough.insert("target".to_owned(), Value::Table(runners));
+        }
+    }
+
+    passthrough
+}
+
+// Run the binaries of every target through the given command. A cfg() that
+// always holds is the only way to name every target.
+pub fn set_runner(passthrough: &mut Table, runner: &[String]) {
+    let targets = passthrough
+        .entry("target")
+        .or_insert_with(|| Value::Table(Table::new()));
+    if let Value::Table(targets) = targets {
+        let mut settings = Table::new();
+        let runner = runner.iter().cloned().map(Value::String).collect();
+        settings.insert("runner".to_owned(), Value::Array(runner));
+        targets.insert("cfg(all())".to_owned(), Value::Table(settings));
+    }
+}
+
+// Where a runner that Cargo would use for the generated project comes from, if
+// any. Cargo sees all of the crate's config, by itself or through load_unseen
+// and passthrough, so the runner cannot be taken out of the way: a
+// target.<triple>.runner would quietly win over set_runner's, and another
+// cfg() runner would make Cargo refuse to pick either.
+pub fn runner_source(config: &Table) -> Option<String> {
+    for (key, _value) in env::vars_os() {
+        if let Some(key) = key.to_str() {
+            if key.starts_with("CARGO_TARGET_") && key.ends_with("_RUNNER") {
+                return Some(key.to_owned());
+            }
+        }
+    }
+    if let Some(Value::Table(targets)) = config.get("target") {
+        for (name, target) in targets {
+            if target.get("runner").is_some() {
+                return Some(format!("target.{:?}.runner in the Cargo config", name));
+            }
+        }
+    }
+    None
+}
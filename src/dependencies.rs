@@ -6,9 +6,8 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::BTreeMap as Map;
 use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::path::PathBuf;
-use toml::Value;
+use std::path::{Component, Path, PathBuf};
+use toml::value::{Table, Value};
 
 pub fn get_manifest(manifest_dir: &Path) -> Manifest {
     try_get_manifest(manifest_dir).unwrap_or_default()
@@ -21,10 +20,24 @@ fn try_get_manifest(manifest_dir: &Path) -> Result<Manifest, Error> {
 
     fix_dependencies(&mut manifest.dependencies, manifest_dir);
     fix_dependencies(&mut manifest.dev_dependencies, manifest_dir);
+    for target in manifest.target.values_mut() {
+        fix_dependencies(&mut target.dependencies, manifest_dir);
+        fix_dependencies(&mut target.dev_dependencies, manifest_dir);
+    }
 
     Ok(manifest)
 }
 
+pub fn get_lockfile(dir: &Path) -> Lockfile {
+    try_get_lockfile(dir).unwrap_or_default()
+}
+
+fn try_get_lockfile(dir: &Path) -> Result<Lockfile, Error> {
+    let lockfile_str = fs::read_to_string(dir.join("Cargo.lock"))?;
+    let lockfile = toml::from_str(&lockfile_str)?;
+    Ok(lockfile)
+}
+
 pub fn get_workspace_manifest(manifest_dir: &Path) -> WorkspaceManifest {
     try_get_workspace_manifest(manifest_dir).unwrap_or_default()
 }
@@ -34,16 +47,215 @@ pub fn try_get_workspace_manifest(manifest_dir: &Path) -> Result<WorkspaceManife
     let manifest_str = fs::read_to_string(cargo_toml_path)?;
     let mut manifest: WorkspaceManifest = toml::from_str(&manifest_str)?;
 
+    fix_dependencies(&mut manifest.workspace.dependencies, manifest_dir);
     fix_patches(&mut manifest.patch, manifest_dir);
     fix_replacements(&mut manifest.replace, manifest_dir);
 
     Ok(manifest)
 }
 
-fn fix_dependencies(dependencies: &mut Map<String, Dependency>, dir: &Path) {
-    dependencies.remove("trybuild");
+pub fn fix_dependencies(dependencies: &mut Map<String, Dependency>, dir: &Path) {
+    let trybuild = dependencies
+        .iter()
+        .filter(|(name, dep)| dep.package(name) == "trybuild")
+        .map(|(name, _dep)| name.clone())
+        .collect::<Vec<String>>();
+    for name in trybuild {
+        dependencies.remove(&name);
+    }
     for dep in dependencies.values_mut() {
-        dep.path = dep.path.as_ref().map(|path| dir.join(path));
+        dep.path = dep.path.as_ref().map(|path| absolute(dir, path));
+    }
+}
+
+// "/path/to/crate" + "../helper" -> "/path/to/helper"
+//
+// Resolved lexically rather than through the filesystem so that the paths in
+// diagnostics, where Cargo shows them as given in the manifest, still start
+// with the workspace directory and get normalized to $WORKSPACE.
+fn absolute(dir: &Path, path: &Path) -> PathBuf {
+    let mut absolute = PathBuf::new();
+    for component in dir.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if absolute.file_name().is_some() => {
+                absolute.pop();
+            }
+            component => absolute.push(component.as_os_str()),
+        }
+    }
+    absolute
+}
+
+// Look up a dependency by the name it is given in Cargo.toml or, if it is
+// renamed as in `serde1 = { package = "serde" }`, by the name of the package.
+pub fn find_mut<'a>(
+    dependencies: &'a mut Map<String, Dependency>,
+    name: &str,
+) -> Option<&'a mut Dependency> {
+    let key = if dependencies.contains_key(name) {
+        name.to_owned()
+    } else {
+        dependencies
+            .iter()
+            .find(|(key, dep)| dep.package(key) == name)?
+            .0
+            .clone()
+    };
+    dependencies.get_mut(&key)
+}
+
+// Replace `serde = { workspace = true }` by the workspace's specification of
+// the dependency, with any features added by the member.
+pub fn inherit(dependencies: &mut Map<String, Dependency>, workspace: &Map<String, Dependency>) {
+    for (name, dep) in dependencies.iter_mut() {
+        if dep.rest.get("workspace") != Some(&Value::Boolean(true)) {
+            continue;
+        }
+        let inherited = match workspace.get(name) {
+            Some(inherited) => inherited,
+            None => continue,
+        };
+        dep.rest.remove("workspace");
+        dep.version = inherited.version.clone();
+        dep.path = inherited.path.clone();
+        dep.default_features = inherited.default_features;
+        let mut features = inherited.features.clone();
+        for feature in dep.features.drain(..) {
+            if !features.contains(&feature) {
+                features.push(feature);
+            }
+        }
+        dep.features = features;
+        for (key, value) in &inherited.rest {
+            dep.rest.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+}
+
+// Features of the generated project, one per feature of the crate under test.
+// Each enables the same feature of the crate, as well as whatever the feature
+// enables among the dependencies that were copied into the generated project,
+// so that test cases can use an optional dependency whenever the crate does.
+//
+//     [features]
+//     std = ["serde/std", "dep:regex"]      std = ["krate/std", "serde/std", "dep:regex"]
+//     json = ["dep:serde_json", "std"]  ->  json = ["krate/json", "dep:serde_json", "std"]
+//     [dependencies]                        # optional dependency without dep: syntax
+//     rand = { optional = true }            rand = ["krate/rand", "dep:rand"]
+pub fn forward_features(crate_name: &str, manifest: &Manifest) -> Map<String, Vec<String>> {
+    let mut dependencies = Map::new();
+    let targets = manifest.target.values();
+    for deps in targets
+        .flat_map(|target| vec![&target.dependencies, &target.dev_dependencies])
+        .chain(vec![&manifest.dependencies, &manifest.dev_dependencies])
+    {
+        for (name, dep) in deps {
+            dependencies.insert(name.as_str(), dep);
+        }
+    }
+
+    // Optional dependencies that are never referred to as dep:name in the
+    // crate's features get an implicit feature of the same name.
+    let implicit = dependencies
+        .iter()
+        .filter(|(name, dep)| {
+            dep.rest.get("optional") == Some(&Value::Boolean(true))
+                && !manifest.features.values().any(|enables| {
+                    enables.iter().any(|enable| {
+                        enable.starts_with("dep:") && &enable["dep:".len()..] == **name
+                    })
+                })
+        })
+        .map(|(name, _dep)| (*name).to_owned())
+        .collect::<Vec<String>>();
+
+    let mut features = Map::new();
+    for (feature, enables) in &manifest.features {
+        let mut forward = vec![format!("{}/{}", crate_name, feature)];
+        for enable in enables {
+            let known = if enable.starts_with("dep:") {
+                dependencies.contains_key(&enable["dep:".len()..])
+            } else if let Some(slash) = enable.find('/') {
+                let dep = enable[..slash].trim_end_matches('?');
+                dependencies.contains_key(dep)
+            } else {
+                manifest.features.contains_key(enable) || implicit.contains(enable)
+            };
+            if known {
+                forward.push(enable.clone());
+            }
+        }
+        features.insert(feature.clone(), forward);
+    }
+    for name in implicit {
+        let forward = vec![format!("{}/{}", crate_name, name), format!("dep:{}", name)];
+        features.insert(name, forward);
+    }
+    features
+}
+
+// The resolver that Cargo uses for the workspace, so that features of the test
+// cases' dependencies are unified the same way as when building the crate.
+// Without an explicit one, Cargo goes by the edition of the root package, which
+// need not be the edition of the crate under test, and a virtual workspace
+// gets the original resolver.
+pub fn resolver(workspace: &WorkspaceManifest) -> String {
+    if let Some(resolver) = &workspace.workspace.resolver {
+        return resolver.clone();
+    }
+    let package = match &workspace.package {
+        Some(package) => package,
+        None => return "1".to_owned(),
+    };
+    if let Some(resolver) = &package.resolver {
+        return resolver.clone();
+    }
+    match package.edition {
+        Edition::E2015 | Edition::E2018 => "1",
+        Edition::E2021 => "2",
+        Edition::E2024 => "3",
+    }
+    .to_owned()
+}
+
+// Either `rust-version = "1.56"` or `rust-version.workspace = true`.
+pub fn rust_version(manifest: &Manifest, workspace: &WorkspaceManifest) -> Option<String> {
+    let rust_version = match manifest.package.rust_version.as_ref()? {
+        Value::Table(table) if table.get("workspace") == Some(&Value::Boolean(true)) => {
+            workspace.workspace.package.as_ref()?.get("rust-version")?
+        }
+        rust_version => rust_version,
+    };
+    rust_version.as_str().map(str::to_owned)
+}
+
+// Lint levels of the package, which may be `lints.workspace = true` to use
+// those of the workspace.
+pub fn lints(manifest: &Manifest, workspace: &WorkspaceManifest) -> Option<Map<String, Value>> {
+    let lints = manifest.lints.as_ref()?;
+    if lints.get("workspace") == Some(&Value::Boolean(true)) {
+        workspace.workspace.lints.clone()
+    } else {
+        Some(lints.clone())
+    }
+}
+
+// Cargo only applies [patch] and [replace] from the root of the workspace.
+// Those of the package itself are applied as well, unless the workspace
+// already patches the same crate.
+pub fn merge_patches(workspace: &mut WorkspaceManifest, package: WorkspaceManifest) {
+    for (registry, patches) in package.patch {
+        let merged = workspace
+            .patch
+            .entry(registry)
+            .or_insert_with(|| RegistryPatch { crates: Map::new() });
+        for (name, patch) in patches.crates {
+            merged.crates.entry(name).or_insert(patch);
+        }
+    }
+    for (name, replacement) in package.replace {
+        workspace.replace.entry(name).or_insert(replacement);
     }
 }
 
@@ -51,7 +263,7 @@ fn fix_patches(patches: &mut Map<String, RegistryPatch>, dir: &Path) {
     for registry in patches.values_mut() {
         registry.crates.remove("trybuild");
         for patch in registry.crates.values_mut() {
-            patch.path = patch.path.as_ref().map(|path| dir.join(path));
+            patch.path = patch.path.as_ref().map(|path| absolute(dir, path));
         }
     }
 }
@@ -59,16 +271,30 @@ fn fix_patches(patches: &mut Map<String, RegistryPatch>, dir: &Path) {
 fn fix_replacements(replacements: &mut Map<String, Patch>, dir: &Path) {
     replacements.remove("trybuild");
     for replacement in replacements.values_mut() {
-        replacement.path = replacement.path.as_ref().map(|path| dir.join(path));
+        replacement.path = replacement.path.as_ref().map(|path| absolute(dir, path));
     }
 }
 
 #[derive(Deserialize, Default, Debug)]
 pub struct WorkspaceManifest {
+    pub package: Option<Package>,
+    #[serde(default)]
+    pub workspace: WorkspaceSection,
     #[serde(default)]
     pub patch: Map<String, RegistryPatch>,
     #[serde(default)]
     pub replace: Map<String, Patch>,
+    #[serde(default)]
+    pub profile: Map<String, Table>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct WorkspaceSection {
+    #[serde(default)]
+    pub dependencies: Map<String, Dependency>,
+    pub lints: Option<Map<String, Value>>,
+    pub resolver: Option<String>,
+    pub package: Option<Map<String, Value>>,
 }
 
 #[derive(Deserialize, Default, Debug)]
@@ -81,12 +307,86 @@ pub struct Manifest {
     pub dependencies: Map<String, Dependency>,
     #[serde(default, alias = "dev-dependencies")]
     pub dev_dependencies: Map<String, Dependency>,
+    #[serde(default)]
+    pub target: Map<String, TargetDependencies>,
+    pub lints: Option<Map<String, Value>>,
+    pub lib: Option<Lib>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct Lib {
+    #[serde(rename = "crate-type", alias = "crate_type")]
+    pub crate_type: Option<Vec<String>>,
+}
+
+impl Manifest {
+    // A library that is only built as a cdylib or staticlib cannot be used
+    // from another crate.
+    pub fn unusable_crate_type(&self) -> Option<&[String]> {
+        let crate_type = self.lib.as_ref()?.crate_type.as_ref()?;
+        let usable = ["lib", "rlib", "dylib", "proc-macro"];
+        if crate_type.iter().any(|ty| usable.contains(&ty.as_str())) {
+            None
+        } else {
+            Some(crate_type)
+        }
+    }
+
+    // Artifact dependencies, like `{ artifact = "bin" }`, are an unstable
+    // feature of Cargo enabled by -Zbindeps.
+    pub fn has_artifact_dependencies(&self) -> bool {
+        let targets = self
+            .target
+            .values()
+            .flat_map(|target| vec![&target.dependencies, &target.dev_dependencies]);
+        vec![&self.dependencies, &self.dev_dependencies]
+            .into_iter()
+            .chain(targets)
+            .flat_map(Map::values)
+            .any(|dep| dep.rest.contains_key("artifact"))
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct TargetDependencies {
+    #[serde(default)]
+    pub dependencies: Map<String, Dependency>,
+    #[serde(default, alias = "dev-dependencies")]
+    pub dev_dependencies: Map<String, Dependency>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct Lockfile {
+    #[serde(default)]
+    pub package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LockedPackage {
+    pub name: String,
+    pub source: Option<String>,
+}
+
+impl LockedPackage {
+    // "git+https://github.com/serde-rs/serde?branch=master#aa8d9f9c"
+    //  -> ("git+https://github.com/serde-rs/serde?branch=master", "aa8d9f9c")
+    pub fn git_commit(&self) -> Option<(&str, &str)> {
+        let source = self.source.as_ref()?;
+        if !source.starts_with("git+") {
+            return None;
+        }
+        let hash = source.rfind('#')?;
+        Some((&source[..hash], &source[hash + 1..]))
+    }
 }
 
 #[derive(Deserialize, Default, Debug)]
 pub struct Package {
     #[serde(default)]
     pub edition: Edition,
+    pub resolver: Option<String>,
+    #[serde(rename = "rust-version")]
+    pub rust_version: Option<Value>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -122,6 +422,19 @@ pub struct Patch {
     pub git: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub branch: Option<String>,
+    #[serde(flatten)]
+    pub rest: Map<String, Value>,
+}
+
+impl Dependency {
+    // Name of the package, which differs from the name of the dependency
+    // if it is renamed.
+    pub fn package<'a>(&'a self, name: &'a str) -> &'a str {
+        match self.rest.get("package") {
+            Some(Value::String(package)) => package,
+            _ => name,
+        }
+    }
 }
 
 fn get_true() -> bool {
@@ -182,3 +495,30 @@ impl<'de> Deserialize<'de> for Dependency {
         deserializer.deserialize_any(DependencyVisitor)
     }
 }
+
+#[test]
+fn test_absolute() {
+    let dir = Path::new("/path/to/crate");
+    let helper = absolute(dir, Path::new("../helper"));
+    assert_eq!(helper, Path::new("/path/to/helper"));
+    let nested = absolute(dir, Path::new("./crates/../nested"));
+    assert_eq!(nested, Path::new("/path/to/crate/nested"));
+}
+
+#[test]
+fn test_resolver() {
+    let resolver = |toml: &str| resolver(&toml::from_str(toml).unwrap());
+    assert_eq!(resolver("[workspace]\nmembers = [\"a\"]\n"), "1");
+    assert_eq!(
+        resolver("[package]\nname = \"a\"\nedition = \"2021\"\n"),
+        "2",
+    );
+    assert_eq!(
+        resolver("[package]\nname = \"a\"\nedition = \"2024\"\n"),
+        "3",
+    );
+    assert_eq!(
+        resolver("[package]\nedition = \"2024\"\n[workspace]\nresolver = \"2\"\n"),
+        "2",
+    );
+}
@@ -1,5 +1,43 @@
+use std::cmp;
+
 pub use self::r#impl::Diff;
 
+// Line-based diff in the style of `diff -u` but without hunk headers, so that
+// the complete expected output remains readable.
+pub fn lines(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // Length of the longest common subsequence of expected[i..] and actual[j..].
+    let mut lcs = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            diff += &format!(" {}\n", expected[i]);
+            i += 1;
+            j += 1;
+        } else if j == actual.len() || i < expected.len() && lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff += &format!("-{}\n", expected[i]);
+            i += 1;
+        } else {
+            diff += &format!("+{}\n", actual[j]);
+            j += 1;
+        }
+    }
+    diff
+}
+
 pub enum Render<'a> {
     Common(&'a str),
     Unique(&'a str),
@@ -73,7 +111,7 @@ mod r#impl {
             None
         }
 
-        pub fn iter(&self, _input: &str) -> Box<dyn Iterator<Item = Render>> {
+        pub fn iter(&self, _input: &str) -> Box<dyn Iterator<Item = Render<'_>>> {
             let _ = Render::Common;
             let _ = Render::Unique;
             match *self {}
@@ -0,0 +1,149 @@
+// Test cases can carry instructions for trybuild in comments of the form:
+//
+//     // trybuild-rust-version: 1.70
+//     // trybuild-manifest: [dependencies]
+//     // trybuild-manifest: anyhow = "1.0"
+//     // trybuild-update: locked
+//     // trybuild-aux-build: helper.rs
+//     // trybuild-crate-type: proc-macro
+//     // trybuild-modules
+//
+//     fn main() {
+//         let Some(x) = std::env::args().next() else { return };
+//     }
+//
+// Lines that do not look like a directive are ignored, as are directives
+// that trybuild does not know about. For test cases borrowed from rustc's test
+// suite, `//@ aux-build: helper.rs` is understood too.
+
+use crate::env::Update;
+use std::fs;
+use std::path::Path;
+
+const PREFIX: &str = "// trybuild-";
+const COMPILETEST_PREFIX: &str = "//@ ";
+
+#[derive(Default, Debug)]
+pub struct Directives {
+    // Oldest compiler that is able to build the test case. Compilers older
+    // than this report the test case as ignored.
+    pub rust_version: Option<Version>,
+    // Lines of TOML to merge into the generated Cargo.toml.
+    pub manifest: Vec<String>,
+    // Takes the place of TRYBUILD for this test case, so that its *.stderr
+    // file is not rewritten along with all the others, or so that its output
+    // never fails the build. "locked" checks the test case as with
+    // TRYBUILD=check, "wip" as with TRYBUILD=wip.
+    pub update: Option<Update>,
+    // Crates in the aux directory next to the test case that it uses as
+    // dependencies, by file name.
+    pub aux_build: Vec<String>,
+    // The test case is the source of a proc-macro crate rather than of a
+    // binary, for testing what a macro's own code is allowed to do.
+    pub proc_macro: bool,
+    // The directory tests/ui/foo/ next to the test case tests/ui/foo.rs holds
+    // the test case's modules.
+    pub modules: bool,
+}
+
+pub type Version = (u32, u32, u32);
+
+pub fn parse(path: &Path) -> Directives {
+    let mut directives = Directives::default();
+
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(_) => return directives,
+    };
+
+    for line in source.lines() {
+        let line = line.trim();
+        let rest = if line.starts_with(PREFIX) {
+            &line[PREFIX.len()..]
+        } else if line.starts_with(COMPILETEST_PREFIX) {
+            let rest = &line[COMPILETEST_PREFIX.len()..];
+            if !rest.starts_with("aux-build") {
+                continue;
+            }
+            rest
+        } else {
+            continue;
+        };
+        let mut parts = rest.splitn(2, ':');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        match key {
+            "rust-version" => directives.rust_version = parse_version(value),
+            "manifest" => directives.manifest.push(value.to_owned()),
+            "aux-build" if !value.is_empty() => directives.aux_build.push(value.to_owned()),
+            "crate-type" => directives.proc_macro = value == "proc-macro",
+            "modules" => directives.modules = true,
+            "update" => {
+                directives.update = match value {
+                    "locked" => Some(Update::Check),
+                    "wip" => Some(Update::Wip),
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    directives
+}
+
+// "1.70" -> (1, 70, 0)
+// "rustc 1.95.0 (f2d3ce0bd 2026-03-21)" -> (1, 95, 0)
+pub fn parse_version(string: &str) -> Option<Version> {
+    let string = string.trim_start_matches("rustc ");
+    let version = string.split(&[' ', '-'][..]).next()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = match parts.next() {
+        Some(patch) => patch.parse().ok()?,
+        None => 0,
+    };
+    Some((major, minor, patch))
+}
+
+// "rustc 1.80.0-nightly (032af18af 2024-06-02)" -> "nightly"
+// "rustc 1.80.0-beta.3 (105fc5a5a 2024-06-01)" -> "beta"
+// "rustc 1.79.0 (129f3b996 2024-06-10)" -> "stable"
+pub fn parse_channel(string: &str) -> Option<&str> {
+    let string = string.trim_start_matches("rustc ");
+    let version = string.split(' ').next()?;
+    parse_version(version)?;
+    let channel = match version.find('-') {
+        Some(dash) => &version[dash + 1..],
+        None => return Some("stable"),
+    };
+    Some(channel.split('.').next().unwrap_or(channel))
+}
+
+#[test]
+fn test_parse_version() {
+    assert_eq!(parse_version("1.70"), Some((1, 70, 0)));
+    assert_eq!(parse_version("1.70.1"), Some((1, 70, 1)));
+    assert_eq!(
+        parse_version("rustc 1.80.0-nightly (032af18af 2024-06-02)"),
+        Some((1, 80, 0)),
+    );
+    assert_eq!(parse_version("latest"), None);
+}
+
+#[test]
+fn test_parse_channel() {
+    assert_eq!(
+        parse_channel("rustc 1.80.0-nightly (032af18af 2024-06-02)"),
+        Some("nightly"),
+    );
+    assert_eq!(
+        parse_channel("rustc 1.80.0-beta.3 (105fc5a5a 2024-06-01)"),
+        Some("beta"),
+    );
+    assert_eq!(
+        parse_channel("rustc 1.79.0 (129f3b996 2024-06-10)"),
+        Some("stable")
+    );
+}
@@ -1,10 +1,24 @@
 use crate::error::{Error, Result};
 use std::env;
 
-#[derive(PartialEq, Debug)]
+/// What to do with the compiler output of a compile_fail test that differs
+/// from its _*.stderr_ file or has no _*.stderr_ file yet.
+///
+/// Normally chosen by the `TRYBUILD` environment variable, or in code with
+/// [`TestCases::update`][crate::TestCases::update].
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Update {
+    /// Write the output of tests without a _*.stderr_ file into the *wip*
+    /// directory. Same as `TRYBUILD=wip`.
     Wip,
+    /// Write the output of every compile_fail test directly into its
+    /// _*.stderr_ file. Same as `TRYBUILD=overwrite`.
     Overwrite,
+    /// Treat a missing _*.stderr_ file as a failure and write nothing. Same as
+    /// `TRYBUILD=check`.
+    Check,
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
 impl Default for Update {
@@ -17,13 +31,52 @@ impl Update {
     pub fn env() -> Result<Self> {
         let var = match env::var_os("TRYBUILD") {
             Some(var) => var,
+            // A forgotten *.stderr file must not let CI pass.
+            None if is_ci() => return Ok(Update::Check),
             None => return Ok(Update::default()),
         };
 
         match var.as_os_str().to_str() {
             Some("wip") => Ok(Update::Wip),
             Some("overwrite") => Ok(Update::Overwrite),
+            Some("check") => Ok(Update::Check),
             _ => Err(Error::UpdateVar(var)),
         }
     }
 }
+
+// Set by GitHub Actions, GitLab CI, Travis, CircleCI, Buildkite and others.
+fn is_ci() -> bool {
+    env::var("CI")
+        .ok()
+        .map_or(false, |var| !var.is_empty() && var != "0" && var != "false")
+}
+
+pub fn offline(default: bool) -> Result<bool> {
+    flag("TRYBUILD_OFFLINE", default)
+}
+
+pub fn jobs(default: Option<usize>) -> Result<Option<usize>> {
+    let var = match env::var_os("TRYBUILD_JOBS") {
+        Some(var) => var,
+        None => return Ok(default),
+    };
+
+    match var.as_os_str().to_str().map(str::parse) {
+        Some(Ok(0)) | Some(Err(_)) | None => Err(Error::Var("TRYBUILD_JOBS", var)),
+        Some(Ok(jobs)) => Ok(Some(jobs)),
+    }
+}
+
+pub fn flag(name: &'static str, default: bool) -> Result<bool> {
+    let var = match env::var_os(name) {
+        Some(var) => var,
+        None => return Ok(default),
+    };
+
+    match var.as_os_str().to_str() {
+        Some("1") | Some("true") => Ok(true),
+        Some("0") | Some("false") => Ok(false),
+        _ => Err(Error::Var(name, var)),
+    }
+}
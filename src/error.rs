@@ -1,5 +1,6 @@
 use glob::{GlobError, PatternError};
 use std::env;
+use std::error::Error as StdError;
 use std::ffi::OsString;
 use std::fmt::{self, Display};
 use std::io;
@@ -11,21 +12,74 @@ pub enum Error {
     CargoFail,
     Glob(GlobError),
     Io(io::Error),
+    MalformedStderr,
     Metadata(serde_json::Error),
-    Mismatch,
+    Mismatch(Mismatch),
+    MissingStderr,
     Open(PathBuf, io::Error),
     Pattern(PatternError),
     PkgName(env::VarError),
     ProjectDir,
     ReadStderr(io::Error),
+    Regex(regex::Error),
     RunFailed,
+    RunnerConflict(String),
+    Rustc(io::Error),
+    RustcFail,
     ShouldNotHaveCompiled,
+    StrayFiles(Vec<PathBuf>),
     TomlDe(toml::de::Error),
     TomlSer(toml::ser::Error),
+    UnknownDependency(String),
+    UnusableCrateType(Vec<String>),
     UpdateVar(OsString),
+    Var(&'static str, OsString),
     WriteStderr(io::Error),
 }
 
+#[derive(Clone, Debug)]
+pub struct Mismatch {
+    pub stderr_path: PathBuf,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// What went wrong, as returned by [`Error::kind`][crate::Error::kind].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    /// Cargo could not be executed, for example because it is not installed.
+    CargoMissing,
+    /// Rustc could not be executed to detect its version.
+    RustcMissing,
+    /// Cargo or rustc ran but reported an error, such as a test case that
+    /// should pass failing to compile.
+    Build,
+    /// The output of `cargo metadata` or the environment set up by Cargo for
+    /// the test could not be understood.
+    Metadata,
+    /// Something about the configuration of the test cases is invalid, such
+    /// as a normalization that is not a valid regex or an unknown dependency.
+    Config,
+    /// Reading or writing a file failed.
+    Io,
+    /// The compiler output does not match the *.stderr file.
+    Mismatch,
+    /// A compile_fail test has no *.stderr file.
+    MissingSnapshot,
+    /// The *.stderr file is not in normalized form, with
+    /// [`strict_snapshots`][crate::TestCases::strict_snapshots].
+    MalformedSnapshot,
+    /// A compile_fail test compiled successfully.
+    ShouldNotHaveCompiled,
+    /// A test case that should pass compiled but exited unsuccessfully.
+    RunFailed,
+    /// A test case left files behind, with
+    /// [`deny_stray_files`][crate::TestCases::deny_stray_files].
+    StrayFiles,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 impl Display for Error {
@@ -37,35 +91,134 @@ impl Display for Error {
             CargoFail => write!(f, "cargo reported an error"),
             Glob(e) => write!(f, "{}", e),
             Io(e) => write!(f, "{}", e),
+            MalformedStderr => write!(f, "expected stderr file is not normalized"),
             Metadata(e) => write!(f, "failed to read cargo metadata: {}", e),
-            Mismatch => write!(f, "compiler error does not match expected error"),
+            Mismatch(_) => write!(f, "compiler error does not match expected error"),
+            MissingStderr => write!(f, "expected stderr file does not exist"),
             Open(path, e) => write!(f, "{}: {}", path.display(), e),
             Pattern(e) => write!(f, "{}", e),
             PkgName(e) => write!(f, "failed to detect CARGO_PKG_NAME: {}", e),
             ProjectDir => write!(f, "failed to determine name of project dir"),
             ReadStderr(e) => write!(f, "failed to read stderr file: {}", e),
+            Regex(e) => write!(f, "invalid normalization: {}", e),
             RunFailed => write!(f, "execution of the test case was unsuccessful"),
+            RunnerConflict(source) => write!(
+                f,
+                "run_wrapper cannot be combined with the runner from {}, \
+                 which Cargo would use as well",
+                source,
+            ),
+            Rustc(e) => write!(f, "failed to execute rustc: {}", e),
+            RustcFail => write!(f, "rustc reported an error"),
             ShouldNotHaveCompiled => {
                 write!(f, "expected test case to fail to compile, but it succeeded")
             }
+            StrayFiles(paths) => {
+                write!(f, "test case created files in its working directory:")?;
+                for path in paths {
+                    write!(f, "\n    {}", path.display())?;
+                }
+                Ok(())
+            }
             TomlDe(e) => write!(f, "{}", e),
             TomlSer(e) => write!(f, "{}", e),
+            UnknownDependency(name) => {
+                write!(f, "no dependency named `{}` in Cargo.toml", name)
+            }
+            UnusableCrateType(crate_type) => write!(
+                f,
+                "the crate is built with crate-type = {:?}, which test cases \
+                 cannot use; add \"rlib\" to the crate-type in Cargo.toml",
+                crate_type,
+            ),
             UpdateVar(var) => write!(
                 f,
                 "unrecognized value of TRYBUILD: {:?}",
                 var.to_string_lossy(),
             ),
+            Var(name, var) => write!(
+                f,
+                "unrecognized value of {}: {:?}",
+                name,
+                var.to_string_lossy(),
+            ),
             WriteStderr(e) => write!(f, "failed to write stderr file: {}", e),
         }
     }
 }
 
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        use self::Error::*;
+
+        match self {
+            Cargo(e) | Io(e) | Open(_, e) | ReadStderr(e) | Rustc(e) | WriteStderr(e) => Some(e),
+            Glob(e) => Some(e),
+            Metadata(e) => Some(e),
+            Pattern(e) => Some(e),
+            PkgName(e) => Some(e),
+            Regex(e) => Some(e),
+            TomlDe(e) => Some(e),
+            TomlSer(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
 impl Error {
+    /// Broad category of the error, for matching on without depending on
+    /// the details of what went wrong.
+    pub fn kind(&self) -> ErrorKind {
+        use self::Error::*;
+
+        match self {
+            Cargo(_) => ErrorKind::CargoMissing,
+            Rustc(_) => ErrorKind::RustcMissing,
+            CargoFail | RustcFail => ErrorKind::Build,
+            Metadata(_) | PkgName(_) | ProjectDir => ErrorKind::Metadata,
+            Pattern(_) | Regex(_) | TomlDe(_) | TomlSer(_) | UnknownDependency(_)
+            | RunnerConflict(_) | UnusableCrateType(_) | UpdateVar(_) | Var(..) => {
+                ErrorKind::Config
+            }
+            Glob(_) | Io(_) | Open(..) | ReadStderr(_) | WriteStderr(_) => ErrorKind::Io,
+            Mismatch(_) => ErrorKind::Mismatch,
+            MissingStderr => ErrorKind::MissingSnapshot,
+            MalformedStderr => ErrorKind::MalformedSnapshot,
+            ShouldNotHaveCompiled => ErrorKind::ShouldNotHaveCompiled,
+            RunFailed => ErrorKind::RunFailed,
+            StrayFiles(_) => ErrorKind::StrayFiles,
+        }
+    }
+
+    // A few words for the list of failed tests printed at the end of the run.
+    pub fn summary(&self) -> String {
+        use self::Error::*;
+
+        match self {
+            CargoFail => "failed to build".to_owned(),
+            MalformedStderr => "malformed stderr file".to_owned(),
+            Mismatch(_) => "mismatch".to_owned(),
+            MissingStderr => "missing stderr file".to_owned(),
+            RunFailed => "failed at runtime".to_owned(),
+            ShouldNotHaveCompiled => "compiled successfully".to_owned(),
+            StrayFiles(_) => "created stray files".to_owned(),
+            _ => {
+                let message = self.to_string();
+                message.lines().next().unwrap_or("").to_owned()
+            }
+        }
+    }
+
     pub fn already_printed(&self) -> bool {
         use self::Error::*;
 
         match self {
-            CargoFail | Mismatch | RunFailed | ShouldNotHaveCompiled => true,
+            CargoFail
+            | Mismatch(_)
+            | MissingStderr
+            | RunFailed
+            | RustcFail
+            | ShouldNotHaveCompiled => true,
             _ => false,
         }
     }
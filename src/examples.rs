@@ -0,0 +1,307 @@
+// Fenced code blocks of a markdown file such as README.md, or of the doc
+// comments of a Rust source file, turned into test cases. Each Rust block is
+// a test case of its own in a directory of the caller's choosing, where its
+// *.stderr file lives like any other test case's:
+//
+//     ```compile_fail            ->  tests/readme/README-3f2a9c1e.rs  compile_fail
+//     ```rust                    ->  tests/readme/README-b07d4410.rs  pass
+//     ```ignore / no_run / text  ->  not tested
+//
+// Only the snapshots are in that directory. The code of the blocks is written
+// to the same path under target/tests/<crate>/examples, next to the generated
+// project, and built from there.
+//
+// Files are named after a hash of the code rather than after the position of
+// the block, so that adding or removing a block does not pair the *.stderr
+// files of the ones after it with different code. Changing an example gives it
+// a new file, and `cargo trybuild prune` takes care of the old snapshot.
+//
+// Blocks follow the rules of rustdoc: a block with no language is Rust, lines
+// starting with `# ` are part of the code though hidden from the rendered
+// docs, and code without a `fn main` goes inside of one. Unused code is
+// allowed, as in doctests.
+
+use crate::error::{Error, Result};
+use crate::hash;
+use crate::{Expected, Test};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(PartialEq, Debug)]
+struct Example {
+    // Line of the opening fence in the file the example came from.
+    line: usize,
+    expected: Expected,
+    code: String,
+}
+
+// Where the examples of the crate are written, given the target directory.
+pub(crate) fn scratch_dir(target_dir: &Path, crate_name: &str) -> PathBuf {
+    path!(target_dir / "tests" / crate_name / "examples")
+}
+
+// Extracts the examples of `source` into `scratch_dir`, as test cases in `dir`.
+pub(crate) fn extract(source: &Path, dir: &Path, scratch_dir: &Path) -> Result<Vec<Test>> {
+    let contents = fs::read_to_string(source).map_err(|err| Error::Open(source.to_owned(), err))?;
+    let is_rust = source.extension().map_or(false, |ext| ext == "rs");
+    let markdown = if is_rust {
+        doc_comments(&contents)
+    } else {
+        contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line))
+            .collect()
+    };
+
+    let stem = source.file_stem().map_or_else(Default::default, |stem| {
+        stem.to_string_lossy().replace('.', "-")
+    });
+    // Never anywhere outside of the scratch directory, even for a `dir` that
+    // is absolute or goes up with `..`.
+    let mut extracted_dir = scratch_dir.to_owned();
+    for component in dir.components() {
+        if let Component::Normal(name) = component {
+            extracted_dir.push(name);
+        }
+    }
+    fs::create_dir_all(&extracted_dir).map_err(|err| Error::Open(extracted_dir.clone(), err))?;
+
+    let header = format!("// Extracted from {}:", source.display());
+    let mut tests: Vec<Test> = Vec::new();
+    for example in examples(&markdown) {
+        let hash = hash::hash(example.code.as_bytes());
+        let file_name = format!("{}-{:08x}.rs", stem, hash as u32);
+        let path = dir.join(&file_name);
+        let extracted = extracted_dir.join(&file_name);
+        // The same code twice is only tested once.
+        if tests.iter().any(|test| test.path == path) {
+            continue;
+        }
+        let contents = format!(
+            "{}{}. Edit the example there instead.\n\n{}",
+            header, example.line, example.code,
+        );
+        if fs::read_to_string(&extracted).ok() != Some(contents.clone()) {
+            fs::write(&extracted, contents).map_err(|err| Error::Open(extracted.clone(), err))?;
+        }
+        tests.push(Test {
+            path,
+            expected: example.expected,
+            extracted: Some(extracted),
+        });
+    }
+
+    remove_stale(&extracted_dir, &header, &tests)?;
+    Ok(tests)
+}
+
+// Files extracted from examples that have since been changed or deleted, as
+// told by their first line, so that `cargo trybuild prune` reports their
+// *.stderr files. Examples of other sources in the same directory stay.
+fn remove_stale(dir: &Path, header: &str, tests: &[Test]) -> Result<()> {
+    let entries = fs::read_dir(dir).map_err(|err| Error::Open(dir.to_owned(), err))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map_or(true, |ext| ext != "rs")
+            || tests
+                .iter()
+                .any(|test| test.extracted.as_ref() == Some(&path))
+        {
+            continue;
+        }
+        let extracted = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| contents.lines().next().map(|line| line.starts_with(header)))
+            .unwrap_or(false);
+        if extracted {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+// The text of `///` and `//!` comments with their line numbers, one block of
+// consecutive comment lines after another.
+fn doc_comments(source: &str) -> Vec<(usize, &str)> {
+    let mut lines = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line = line.trim_start();
+        let is_doc =
+            line.starts_with("///") && !line.starts_with("////") || line.starts_with("//!");
+        if !is_doc {
+            continue;
+        }
+        let comment = &line[3..];
+        let comment = if comment.starts_with(' ') {
+            &comment[1..]
+        } else {
+            comment
+        };
+        lines.push((i + 1, comment));
+    }
+    lines
+}
+
+fn examples(markdown: &[(usize, &str)]) -> Vec<Example> {
+    let mut examples = Vec::new();
+    let mut lines = markdown.iter();
+
+    while let Some(&(line, text)) = lines.next() {
+        let indent = text.len() - text.trim_start().len();
+        let text = text.trim_start();
+        let fence_char = match text.chars().next() {
+            Some(ch @ '`') | Some(ch @ '~') => ch,
+            _ => continue,
+        };
+        let fence_len = text.chars().take_while(|&ch| ch == fence_char).count();
+        if fence_len < 3 {
+            continue;
+        }
+        let info = &text[fence_len..];
+
+        let mut code = String::new();
+        for &(_, text) in lines.by_ref() {
+            let trimmed = text.trim();
+            if trimmed.len() >= fence_len && trimmed.chars().all(|ch| ch == fence_char) {
+                break;
+            }
+            let text = strip_indent(text, indent);
+            code += unhide(text);
+            code.push('\n');
+        }
+
+        if let Some(expected) = expected(info) {
+            examples.push(Example {
+                line,
+                expected,
+                code: wrap_in_main(&code),
+            });
+        }
+    }
+
+    examples
+}
+
+// Whether a code block with this info string is tested, and how. Anything in
+// a language other than Rust is not.
+fn expected(info: &str) -> Option<Expected> {
+    let mut expected = Some(Expected::Pass);
+    let words = info.split(|ch: char| ch == ',' || ch.is_whitespace());
+    for word in words.filter(|word| !word.is_empty()) {
+        match word {
+            "rust" | "edition2015" | "edition2018" | "edition2021" | "edition2024" => {}
+            "compile_fail" => expected = expected.map(|_| Expected::CompileFail),
+            "ignore" | "no_run" | "should_panic" | "test_harness" => expected = None,
+            word if word.starts_with('E') && word[1..].chars().all(|ch| ch.is_ascii_digit()) => {}
+            _ => return None,
+        }
+    }
+    expected
+}
+
+fn strip_indent(text: &str, indent: usize) -> &str {
+    let leading = text.len() - text.trim_start_matches(' ').len();
+    &text[leading.min(indent)..]
+}
+
+// `# let x = 1;` is code that rustdoc leaves out of the rendered docs, and
+// `## foo` is a line that really does start with `# foo`.
+fn unhide(line: &str) -> &str {
+    let trimmed = line.trim_start();
+    if trimmed == "#" {
+        ""
+    } else if trimmed.starts_with("##") {
+        &trimmed[1..]
+    } else if trimmed.starts_with("# ") {
+        &trimmed[2..]
+    } else {
+        line
+    }
+}
+
+// Crate attributes stay at the top.
+fn wrap_in_main(code: &str) -> String {
+    let mut wrapped = String::from("#![allow(unused)]\n");
+    if code.contains("fn main") {
+        wrapped += code;
+        return wrapped;
+    }
+    let mut in_main = false;
+    for line in code.lines() {
+        if !in_main && !line.trim_start().starts_with("#![") {
+            wrapped += "fn main() {\n";
+            in_main = true;
+        }
+        wrapped += line;
+        wrapped.push('\n');
+    }
+    if !in_main {
+        wrapped += "fn main() {\n";
+    }
+    wrapped += "}\n";
+    wrapped
+}
+
+#[test]
+fn test_examples() {
+    let markdown = "\
+# Example
+
+```
+let x = 1;
+```
+
+```rust,compile_fail
+# use std::collections::HashMap;
+fn main() {
+    let x: i32 = \"\";
+}
+```
+
+```toml
+[dependencies]
+```
+
+```ignore
+broken
+```
+";
+    let lines: Vec<_> = markdown
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .collect();
+    assert_eq!(
+        examples(&lines),
+        vec![
+            Example {
+                line: 3,
+                expected: Expected::Pass,
+                code: "#![allow(unused)]\nfn main() {\nlet x = 1;\n}\n".to_owned(),
+            },
+            Example {
+                line: 7,
+                expected: Expected::CompileFail,
+                code: "#![allow(unused)]\nuse std::collections::HashMap;\nfn main() {\n    let x: i32 = \"\";\n}\n"
+                    .to_owned(),
+            },
+        ],
+    );
+}
+
+#[test]
+fn test_doc_comments() {
+    let source = "\
+//! Crate docs
+/// ```
+/// let x = 1;
+/// ```
+//// not a doc comment
+fn f() {}
+";
+    assert_eq!(
+        doc_comments(source),
+        vec![(1, "Crate docs"), (2, "```"), (3, "let x = 1;"), (4, "```")],
+    );
+}
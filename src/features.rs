@@ -90,6 +90,7 @@ fn try_find() -> Result<Vec<String>, Ignored> {
     Ok(build.features)
 }
 
+#[allow(clippy::manual_range_contains)]
 fn is_lower_hex_digit(byte: u8) -> bool {
     byte >= b'0' && byte <= b'9' || byte >= b'a' && byte <= b'f'
 }
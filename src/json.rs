@@ -0,0 +1,110 @@
+// With TestCases::json_snapshots, compile_fail tests are checked against a
+// *.json file instead of a *.stderr file. It holds the diagnostics that rustc
+// emits with --error-format=json, reduced to what identifies them:
+//
+//     [
+//       {
+//         "children": [],
+//         "code": "E0308",
+//         "level": "error",
+//         "message": "mismatched types",
+//         "spans": [
+//           {
+//             "column_end": 19,
+//             "column_start": 17,
+//             "file": "$DIR/tests/ui/mismatch.rs",
+//             "label": "expected `u8`, found `&str`",
+//             "line_end": 2,
+//             "line_start": 2,
+//             "primary": true
+//           }
+//         ]
+//       }
+//     ]
+//
+// Changes to how rustc renders diagnostics for humans, such as the layout of
+// source snippets, do not affect these snapshots.
+
+use serde_json::{json, Value};
+
+use crate::normalize::{self, Context};
+
+pub fn snapshot(diagnostics: &[Value], context: Context) -> String {
+    let simplified: Vec<Value> = diagnostics
+        .iter()
+        .filter(|diagnostic| !is_summary(diagnostic))
+        .map(|diagnostic| simplify(diagnostic, context))
+        .collect();
+    let mut snapshot = serde_json::to_string_pretty(&simplified).unwrap();
+    snapshot.push('\n');
+    snapshot
+}
+
+// Compares structurally, so that the snapshot may be reformatted by hand.
+pub fn matches(expected: &str, actual: &str) -> bool {
+    let expected: Value = match serde_json::from_str(expected) {
+        Ok(expected) => expected,
+        Err(_) => return false,
+    };
+    let actual: Value = match serde_json::from_str(actual) {
+        Ok(actual) => actual,
+        Err(_) => return false,
+    };
+    expected == actual
+}
+
+// "aborting due to 2 previous errors", "1 warning emitted",
+// "For more information about this error, try `rustc --explain E0308`."
+fn is_summary(diagnostic: &Value) -> bool {
+    let level = diagnostic["level"].as_str().unwrap_or("");
+    let message = diagnostic["message"].as_str().unwrap_or("");
+    let has_spans = diagnostic["spans"]
+        .as_array()
+        .map_or(false, |spans| !spans.is_empty());
+    level == "failure-note"
+        || !has_spans
+            && (message.starts_with("aborting due to ")
+                || message.ends_with(" warning emitted")
+                || message.ends_with(" warnings emitted"))
+}
+
+fn simplify(diagnostic: &Value, context: Context) -> Value {
+    let text = |value: &Value| match value.as_str() {
+        Some(string) => Value::String(normalize::text(string, context)),
+        None => Value::Null,
+    };
+
+    let spans: Vec<Value> = diagnostic["spans"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+        .iter()
+        .map(|span| {
+            json!({
+                "file": text(&span["file_name"]),
+                "line_start": span["line_start"],
+                "column_start": span["column_start"],
+                "line_end": span["line_end"],
+                "column_end": span["column_end"],
+                "label": text(&span["label"]),
+                "primary": span["is_primary"],
+            })
+        })
+        .collect();
+
+    let children: Vec<Value> = diagnostic["children"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+        .iter()
+        .map(|child| simplify(child, context))
+        .collect();
+
+    json!({
+        "level": diagnostic["level"],
+        "code": diagnostic["code"]["code"],
+        "message": text(&diagnostic["message"]),
+        "spans": spans,
+        "children": children,
+    })
+}
@@ -103,7 +103,8 @@
 //!
 //! # Details
 //!
-//! That's the entire API.
+//! That's the entire API for writing tests. The remaining methods on
+//! [`TestCases`] adjust how the test cases are built.
 //!
 //! <br>
 //!
@@ -117,7 +118,8 @@
 //! compiler output with the right filename into a directory called *wip* within
 //! the directory containing Cargo.toml. So you can update these files by
 //! deleting them, running `cargo test`, and moving all the files from *wip*
-//! into your testcase directory.
+//! into your testcase directory. A different directory can be chosen with
+//! [`TestCases::wip_dir`] or the `TRYBUILD_WIP_DIR` environment variable.
 //!
 //! <p align="center">
 //! <img src="https://user-images.githubusercontent.com/1940490/57186579-7cd51580-6e96-11e9-9f19-54dcecc9fbba.png" width="700">
@@ -128,6 +130,99 @@
 //! output directly in place. You'll want to check `git diff` afterward to be
 //! sure the compiler's output is what you had in mind.
 //!
+//! With `TRYBUILD=check`, a missing _*.stderr_ file is a test failure instead
+//! of being written to *wip*. This is the default when the `CI` environment
+//! variable is set, so that a snapshot which was never committed cannot let
+//! CI pass.
+//!
+//! The same choice can be made in code with [`TestCases::update`], for example
+//! behind a project-specific environment variable.
+//!
+//! When running a single test case over and over while working on it, set
+//! `TRYBUILD_WARM=1` to have trybuild remember Cargo's view of the workspace
+//! between runs rather than querying Cargo every time. It is kept in the
+//! target directory and forgotten whenever a manifest, Cargo.lock, a Cargo
+//! config file or the toolchain changes. The dependencies of the test cases
+//! are only rebuilt when they or the crate under test change, with or without
+//! it.
+//!
+//! `TRYBUILD_ARCHIVE=record` keeps the raw compiler output of every test case
+//! in _wip/archive_, and a later run with `TRYBUILD_ARCHIVE=replay`
+//! normalizes and compares that output again without building anything.
+//! Together with `TRYBUILD=overwrite` this migrates all the _*.stderr_ files
+//! after a change to the normalizations in seconds. Test cases that should
+//! pass are ignored when replaying.
+//!
+//! Parts of a _*.stderr_ file that are expected to change for reasons outside
+//! of your control, such as source code quoted from a dependency, can be
+//! placed between a line `// trybuild-volatile-begin` and a line
+//! `// trybuild-volatile-end`. Such a section matches any compiler output at
+//! that position. Overwriting the file discards the markers.
+//!
+//! If the compiler output alternates between a few known forms, for example in
+//! the order of its diagnostics, commit each of the others as _*.alt1.stderr_,
+//! _*.alt2.stderr_ and so on. The test passes if the output matches any of
+//! them. `TRYBUILD=overwrite` only ever updates the _*.stderr_ file itself.
+//!
+//! A test case can fix how its _*.stderr_ file is updated, whatever `TRYBUILD`
+//! says: with a line `// trybuild-update: locked` it is always checked as with
+//! `TRYBUILD=check`, so that its snapshot is not rewritten by accident, and with
+//! `// trybuild-update: wip` its output always goes to _wip_ as with
+//! `TRYBUILD=wip`, in CI too.
+//!
+//! A test case that relies on language features newer than the crate's minimum
+//! supported Rust version can say so with a line `// trybuild-rust-version:
+//! 1.70`. It is reported as ignored when built with an older compiler.
+//!
+//! Extra settings for the generated Cargo.toml that only one test case needs,
+//! such as an additional dependency, go in a file next to the test case with
+//! the same name and the extension _.toml_, or in `// trybuild-manifest:` lines
+//! at the top of the test case. A `[bin]` table in there applies to the test
+//! case's own binary target. Test cases with other settings are built in a
+//! separate project.
+//!
+//! A test case tests/ui/foo.rs with a line `// trybuild-modules` can be split
+//! into modules: `mod helper;` in it refers to _tests/ui/foo/helper.rs_.
+//!
+//! For tests that need a second crate, such as of the orphan rule, put the
+//! helper crate's source in a directory called _aux_ next to the test case and
+//! add a line `// trybuild-aux-build: helper.rs` to the test case, which then
+//! refers to it as `helper`. The rustc test suite's spelling `//@ aux-build:
+//! helper.rs` works too. Such a test case is built in a separate project, so
+//! that its helper crates are not visible to the other test cases.
+//!
+//! Examples in a README that are supposed to fail to compile can be pinned
+//! with their exact errors: `t.doc_examples("README.md", "tests/readme")`
+//! tests each Rust code block of README.md as pass or compile_fail according
+//! to how the block is marked, with the usual _*.stderr_ files in
+//! tests/readme. The doc comments of a Rust source file work too.
+//!
+//! A test case with a line `// trybuild-crate-type: proc-macro` is itself
+//! built as a proc-macro crate instead of a binary, for testing what the
+//! source of a macro is allowed to do, such as that it cannot be used from
+//! within the crate that defines it. It has the same dependencies as other
+//! test cases.
+//!
+//! What cannot be expressed as a single file, such as a build script, a
+//! library with several crate types, or an error about the manifest or the
+//! workspace, can be tested with a directory containing a Cargo.toml and src/
+//! of its own: `t.compile_fail("tests/ui/bad-crate-type")`. Such a test case
+//! is built as a standalone crate, in place and with `cargo build`, and its
+//! snapshot is _tests/ui/bad-crate-type.stderr_. A passing one is run if it
+//! has a binary. Put an empty `[workspace]` table in its Cargo.toml unless it
+//! is meant to be part of an enclosing workspace.
+//!
+//! Where the compiler output legitimately differs between release channels,
+//! commit a _*.nightly.stderr_ or _*.beta.stderr_ file next to the
+//! _*.stderr_ file. It takes precedence when the test cases are built by a
+//! compiler from that channel. Likewise for output that differs between
+//! platforms, a _*.windows.stderr_, _*.macos.stderr_ or _*.linux.stderr_ file
+//! takes precedence when building for that target OS.
+//!
+//! Under `cargo llvm-cov`, or with `-C instrument-coverage` in RUSTFLAGS, the
+//! test cases are instrumented too, and the code that passing test cases run
+//! is counted in the coverage report along with that of the tests themselves.
+//!
 //! <br>
 //!
 //! # What to test
@@ -208,27 +303,58 @@
 //! makes error messages that we care about substantially worse, it is also
 //! important to catch and report as a compiler issue.
 
+// The examples above show a #[test] function the way it is written in a test
+// file. They are compiled as doctests without running it.
+#![allow(clippy::test_attr_in_doctest)]
+
 #[macro_use]
 mod term;
 
 #[macro_use]
 mod path;
 
+#[macro_use]
+mod trace;
+
+mod archive;
 mod cargo;
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod cli;
+mod config;
 mod dependencies;
 mod diff;
+mod directives;
 mod env;
 mod error;
+mod examples;
 mod features;
+mod hash;
+mod json;
+mod lock;
 mod manifest;
 mod message;
 mod normalize;
+mod pending;
+#[cfg(feature = "cli")]
+mod review;
 mod run;
 mod rustflags;
+mod timings;
+mod volatile;
+mod warm;
+#[cfg(feature = "cli")]
+mod watch;
+
+pub use crate::env::Update;
+pub use crate::error::ErrorKind;
 
 use std::cell::RefCell;
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
 use std::path::{Path, PathBuf};
 use std::thread;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct TestCases {
@@ -238,25 +364,124 @@ pub struct TestCases {
 #[derive(Debug)]
 struct Runner {
     tests: Vec<Test>,
+    doc_examples: Vec<(PathBuf, PathBuf)>,
+    target: Option<String>,
+    roots: Vec<(String, PathBuf)>,
+    normalizations: Vec<(String, String)>,
+    toolchains: Vec<String>,
+    share_target_dir: bool,
+    rustc_wrapper: Option<PathBuf>,
+    run_wrapper: Option<Vec<String>>,
+    rustc: Option<PathBuf>,
+    update: Option<Update>,
+    offline: bool,
+    cargo_args: Vec<String>,
+    share_lockfile: bool,
+    strict_snapshots: bool,
+    ignore_notes: bool,
+    ignore_help: bool,
+    ignore_feature_gates: bool,
+    json_diagnostics: bool,
+    json_snapshots: bool,
+    ansi_snapshots: bool,
+    color: Option<bool>,
+    max_width: Option<usize>,
+    wip_dir: Option<PathBuf>,
+    verbose: Vec<PathBuf>,
+    deny_stray_files: bool,
+    skip_all: bool,
+    release: bool,
+    profiles: Vec<(String, String)>,
+    lints: Vec<(String, String)>,
+    timings: bool,
+    jobs: Option<usize>,
+    dependency_features: Vec<(String, Vec<String>)>,
+    dependency_default_features: Vec<(String, bool)>,
+    build_script: Option<BuildScript>,
+    build_dependencies: Vec<String>,
+    dev_dependencies: Option<Vec<String>>,
+    crate_default_features: bool,
+    crate_features: Vec<String>,
+    features: Option<Vec<String>>,
+    auto_features: bool,
+    all_features: bool,
+    default_features: bool,
+    feature_sets: Vec<Vec<String>>,
+    finished: bool,
 }
 
 #[derive(Clone, Debug)]
 struct Test {
     path: PathBuf,
     expected: Expected,
+    // The file that an example from doc_examples was written to, in the
+    // target directory. `path` is where it would be next to its snapshot.
+    extracted: Option<PathBuf>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 enum Expected {
     Pass,
     CompileFail,
 }
 
+#[derive(Clone, Debug)]
+enum BuildScript {
+    Path(PathBuf),
+    Source(String),
+}
+
 impl TestCases {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         TestCases {
-            runner: RefCell::new(Runner { tests: Vec::new() }),
+            runner: RefCell::new(Runner {
+                tests: Vec::new(),
+                doc_examples: Vec::new(),
+                target: None,
+                roots: Vec::new(),
+                normalizations: Vec::new(),
+                toolchains: Vec::new(),
+                share_target_dir: false,
+                rustc_wrapper: None,
+                run_wrapper: None,
+                rustc: None,
+                update: None,
+                offline: true,
+                cargo_args: Vec::new(),
+                share_lockfile: false,
+                strict_snapshots: false,
+                ignore_notes: false,
+                ignore_help: false,
+                ignore_feature_gates: false,
+                json_diagnostics: false,
+                json_snapshots: false,
+                ansi_snapshots: false,
+                color: None,
+                max_width: None,
+                wip_dir: None,
+                verbose: Vec::new(),
+                deny_stray_files: false,
+                skip_all: false,
+                release: false,
+                profiles: Vec::new(),
+                lints: Vec::new(),
+                timings: false,
+                jobs: None,
+                dependency_features: Vec::new(),
+                dependency_default_features: Vec::new(),
+                build_script: None,
+                build_dependencies: Vec::new(),
+                dev_dependencies: None,
+                crate_default_features: false,
+                crate_features: Vec::new(),
+                features: None,
+                auto_features: true,
+                all_features: false,
+                default_features: true,
+                feature_sets: Vec::new(),
+                finished: false,
+            }),
         }
     }
 
@@ -264,6 +489,7 @@ impl TestCases {
         self.runner.borrow_mut().tests.push(Test {
             path: path.as_ref().to_owned(),
             expected: Expected::Pass,
+            extracted: None,
         });
     }
 
@@ -271,15 +497,839 @@ impl TestCases {
         self.runner.borrow_mut().tests.push(Test {
             path: path.as_ref().to_owned(),
             expected: Expected::CompileFail,
+            extracted: None,
         });
     }
+
+    /// Test the Rust code blocks of a markdown file such as README.md, or of
+    /// the doc comments in a Rust source file, as pass or compile_fail test
+    /// cases according to how each block is marked.
+    ///
+    /// Every block is a test case of its own in `dir`, named after the source
+    /// and a hash of the code, such as _tests/readme/README-3f2a9c1e.rs_. The
+    /// code is only written to the target directory, so `dir` holds nothing
+    /// but the snapshots: a block marked `compile_fail` needs a *.stderr* file
+    /// there like any other compile_fail test case, which pins the exact error
+    /// that the example is supposed to fail with. Blocks marked `ignore`, `no_run` or
+    /// `should_panic`, and blocks in a language other than Rust, are not
+    /// tested. As in doctests, lines starting with `# ` are part of the code,
+    /// and code without a `fn main` is put inside of one.
+    ///
+    /// ```no_run
+    /// let t = trybuild::TestCases::new();
+    /// t.doc_examples("README.md", "tests/readme");
+    /// ```
+    pub fn doc_examples<P: AsRef<Path>, D: AsRef<Path>>(&self, source: P, dir: D) {
+        self.runner
+            .borrow_mut()
+            .doc_examples
+            .push((source.as_ref().to_owned(), dir.as_ref().to_owned()));
+    }
+
+    /// Build the test cases for the given target instead of the host.
+    ///
+    /// The target may be either a target triple like `"thumbv7em-none-eabi"`
+    /// or the path of a custom target specification JSON file, relative to
+    /// the directory containing Cargo.toml. Occurrences of a target
+    /// specification's path in compiler output are normalized to `$TARGET`.
+    ///
+    /// By default the target is taken from `CARGO_BUILD_TARGET` or the
+    /// `build.target` setting of the crate's Cargo configuration, if any.
+    pub fn target<T: AsRef<str>>(&self, target: T) {
+        self.runner.borrow_mut().target = Some(target.as_ref().to_owned());
+    }
+
+    /// Register a directory outside of the crate's own directory, such as a
+    /// directory of test cases shared by several crates.
+    ///
+    /// Test cases may be loaded from anywhere, but only paths under the
+    /// directory containing Cargo.toml are normalized to `$DIR` in compiler
+    /// output. Paths under `dir` are instead normalized to `$` followed by
+    /// `name`, so `t.source_root("SHARED", "../shared-ui")` turns them into
+    /// `$SHARED`.
+    ///
+    /// The directory does not need to contain test cases. Any directory that
+    /// shows up in compiler output, such as the root of a monorepo or a
+    /// directory of generated code, can be given a placeholder this way. Can
+    /// be called more than once.
+    pub fn source_root<P: AsRef<Path>>(&self, name: &str, dir: P) {
+        self.runner
+            .borrow_mut()
+            .roots
+            .push((name.to_owned(), dir.as_ref().to_owned()));
+    }
+
+    /// Replace every match of a regular expression in compiler output before
+    /// comparing it against the expected *.stderr* file.
+    ///
+    /// This is for project-specific text that changes from one build to the
+    /// next, such as timestamps or generated identifiers. The replacement may
+    /// refer to capture groups as `$1` or `$name`. Normalizations run after
+    /// the built-in ones, in the order they were added.
+    ///
+    /// ```no_run
+    /// # let t = trybuild::TestCases::new();
+    /// t.normalize(r"__generated_[0-9a-f]{8}", "__generated_$$HASH");
+    /// ```
+    pub fn normalize(&self, regex: &str, replacement: &str) {
+        self.runner
+            .borrow_mut()
+            .normalizations
+            .push((regex.to_owned(), replacement.to_owned()));
+    }
+
+    /// Build the test cases with a particular rustup toolchain, such as
+    /// `"nightly"` or `"nightly-2024-06-01"`, rather than the toolchain that
+    /// is running the test harness.
+    ///
+    /// This invokes `cargo +<toolchain>` and so requires rustup.
+    pub fn toolchain<T: AsRef<str>>(&self, toolchain: T) {
+        self.runner.borrow_mut().toolchains = vec![toolchain.as_ref().to_owned()];
+    }
+
+    /// Run every test case once per toolchain in the list.
+    ///
+    /// A compile_fail test checks its output against a snapshot named after
+    /// the compiler's release channel if one exists, for example
+    /// *foo.nightly.stderr* when running `"nightly-2024-06-01"`, falling back
+    /// to *foo.stderr* otherwise. With `TRYBUILD=overwrite`, output that
+    /// matches neither is written to *foo.nightly.stderr*, leaving
+    /// *foo.stderr* to the other toolchains.
+    pub fn toolchains<I>(&self, toolchains: I)
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.runner.borrow_mut().toolchains = toolchains
+            .into_iter()
+            .map(|toolchain| toolchain.as_ref().to_owned())
+            .collect();
+    }
+
+    /// Build the test cases in the same target directory as the rest of the
+    /// workspace instead of a separate one under *target/tests*.
+    ///
+    /// Dependencies that were already compiled for `cargo test` with the same
+    /// compiler, features and flags are then reused rather than rebuilt, which
+    /// substantially reduces the time to prepare the test cases. Note that
+    /// trybuild compiles with `--cfg trybuild`, so this pays off most when the
+    /// workspace is built with the same RUSTFLAGS.
+    pub fn share_target_dir(&self) {
+        self.runner.borrow_mut().share_target_dir = true;
+    }
+
+    /// Invoke rustc through a wrapper such as `sccache` when building the
+    /// test cases, as if by setting `RUSTC_WRAPPER`.
+    ///
+    /// Without this, `RUSTC_WRAPPER`, `RUSTC_WORKSPACE_WRAPPER` and any
+    /// wrapper-specific environment variables like `SCCACHE_DIR` are passed
+    /// through from the environment of the test harness.
+    pub fn rustc_wrapper<P: AsRef<Path>>(&self, wrapper: P) {
+        self.runner.borrow_mut().rustc_wrapper = Some(wrapper.as_ref().to_owned());
+    }
+
+    /// Run the binaries of passing test cases through a command such as
+    /// `valgrind --error-exitcode=1` or `rr record`, given as the program
+    /// followed by its arguments. The command is in turn given the path of
+    /// the binary and the binary's arguments.
+    ///
+    /// ```no_run
+    /// # let t = trybuild::TestCases::new();
+    /// t.run_wrapper(&["valgrind", "--error-exitcode=1", "--leak-check=full"]);
+    /// ```
+    ///
+    /// The command's own output is captured along with that of the test
+    /// case, and the test case fails if the command exits unsuccessfully, as
+    /// valgrind does on memory errors when asked to.
+    ///
+    /// Cargo has no way to set aside a `runner` from the crate's Cargo config
+    /// or a `CARGO_TARGET_<triple>_RUNNER` variable, so the tests fail with
+    /// an error if either is present.
+    pub fn run_wrapper<I, S>(&self, command: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let command = command
+            .into_iter()
+            .map(|arg| arg.as_ref().to_owned())
+            .collect();
+        self.runner.borrow_mut().run_wrapper = Some(command);
+    }
+
+    /// Build the test cases with a specific rustc binary, such as a locally
+    /// built compiler, as if by setting `RUSTC`. Rustup is bypassed.
+    ///
+    /// A relative path is interpreted relative to the directory containing
+    /// Cargo.toml. Paths under the compiler's sysroot in compiler output are
+    /// normalized to `$SYSROOT`.
+    pub fn rustc<P: AsRef<Path>>(&self, rustc: P) {
+        self.runner.borrow_mut().rustc = Some(rustc.as_ref().to_owned());
+    }
+
+    /// Whether cargo may access the network while building the test cases.
+    ///
+    /// By default cargo runs with `--offline`, which requires dependencies to
+    /// already be in the local cargo cache. Passing `false` allows them to be
+    /// downloaded. The environment variable `TRYBUILD_OFFLINE=0` or
+    /// `TRYBUILD_OFFLINE=1` takes precedence over this setting.
+    pub fn offline(&self, offline: bool) {
+        self.runner.borrow_mut().offline = offline;
+    }
+
+    /// Pass an extra argument to every cargo build, check and run of the test
+    /// cases, for example `"--config=net.git-fetch-with-cli=true"` or
+    /// `"-Zdirect-minimal-versions"`.
+    pub fn cargo_arg<A: AsRef<str>>(&self, arg: A) {
+        self.runner
+            .borrow_mut()
+            .cargo_args
+            .push(arg.as_ref().to_owned());
+    }
+
+    /// Start from a copy of the workspace's Cargo.lock rather than resolving
+    /// the latest compatible version of every dependency, so that the test
+    /// cases are built against exactly the same dependency versions as the
+    /// rest of the workspace.
+    pub fn share_lockfile(&self) {
+        self.runner.borrow_mut().share_lockfile = true;
+    }
+
+    /// Fail compile_fail tests whose *.stderr file is not byte for byte in
+    /// the form trybuild would write it.
+    ///
+    /// By default a leading byte order mark, carriage returns, and a missing
+    /// or repeated newline at the end of a *.stderr file are ignored when
+    /// comparing it against the compiler output, since these are easy to
+    /// introduce by editing the file by hand and impossible to see.
+    pub fn strict_snapshots(&self) {
+        self.runner.borrow_mut().strict_snapshots = true;
+    }
+
+    /// Leave out `note:` lines when comparing compiler output against the
+    /// *.stderr file, so that a test case pins down the error itself but not
+    /// the explanatory notes that rustc attaches to it and frequently
+    /// rewords.
+    ///
+    /// Notes are removed from both the expected and the actual output, so
+    /// existing *.stderr files that do contain them keep passing. New
+    /// *.stderr files are still written with the notes included.
+    pub fn ignore_notes(&self) {
+        self.runner.borrow_mut().ignore_notes = true;
+    }
+
+    /// Leave out `help:` lines and the suggestions they show when comparing
+    /// compiler output against the *.stderr file.
+    ///
+    /// The wording and rendering of suggestions change in almost every Rust
+    /// release. As with [`ignore_notes`][TestCases::ignore_notes], they are
+    /// removed from both the expected and the actual output.
+    pub fn ignore_help(&self) {
+        self.runner.borrow_mut().ignore_help = true;
+    }
+
+    /// Leave out the lines that nightly compilers add to errors about
+    /// unstable features when comparing compiler output against the
+    /// *.stderr file, such as "add `#![feature(...)]` to the crate
+    /// attributes to enable", so that the same *.stderr files pass on stable
+    /// and nightly.
+    pub fn ignore_feature_gates(&self) {
+        self.runner.borrow_mut().ignore_feature_gates = true;
+    }
+
+    /// Compare only the diagnostics rendered by rustc against the *.stderr
+    /// file, without any status lines or warnings that Cargo prints on its
+    /// own behalf.
+    ///
+    /// Warnings from compiling dependencies of the test case are left out
+    /// either way.
+    pub fn json_diagnostics(&self) {
+        self.runner.borrow_mut().json_diagnostics = true;
+    }
+
+    /// Check compile_fail tests against a *.json file holding rustc's
+    /// diagnostics in structured form, instead of against the rendered
+    /// compiler output in a *.stderr file.
+    ///
+    /// Each diagnostic is recorded with its level, error code, message, and
+    /// the location and label of each span, as well as the notes and help
+    /// attached to it. The files are compared structurally, so the test
+    /// cases are unaffected by changes to the way rustc renders diagnostics
+    /// for humans.
+    pub fn json_snapshots(&self) {
+        self.runner.borrow_mut().json_snapshots = true;
+    }
+
+    /// Build the test cases with `--color=always` and keep the ANSI color
+    /// escape sequences in the compiler output that is compared against the
+    /// *.stderr file.
+    ///
+    /// This is for crates whose own diagnostics, for example ones emitted by
+    /// a procedural macro, are colored in a way worth verifying.
+    pub fn ansi_snapshots(&self) {
+        self.runner.borrow_mut().ansi_snapshots = true;
+    }
+
+    /// Write the output of compile_fail tests that have no *.stderr file yet
+    /// into `dir` instead of *wip*.
+    ///
+    /// A relative path is interpreted relative to the directory containing
+    /// Cargo.toml. The `TRYBUILD_WIP_DIR` environment variable takes
+    /// precedence over this setting.
+    pub fn wip_dir<P: AsRef<Path>>(&self, dir: P) {
+        self.runner.borrow_mut().wip_dir = Some(dir.as_ref().to_owned());
+    }
+
+    /// Always or never color trybuild's own output.
+    ///
+    /// By default output is colored unless the `NO_COLOR` environment
+    /// variable is set or `CLICOLOR` is set to `0`, and `CLICOLOR_FORCE`
+    /// forces color on. This setting takes precedence over all of them.
+    pub fn color(&self, enabled: bool) {
+        self.runner.borrow_mut().color = Some(enabled);
+    }
+
+    /// Fit trybuild's output in at most this many columns.
+    ///
+    /// When printing compiler output and the output of test cases, lines
+    /// that are wider than the terminal, or than the `COLUMNS` environment
+    /// variable if set, or wider than `max_width`, are cut off. The setting
+    /// applies to this `TestCases` only.
+    pub fn max_width(&self, max_width: usize) {
+        self.runner.borrow_mut().max_width = Some(max_width);
+    }
+
+    /// Build the test cases matching the given path or glob pattern without
+    /// passing `--quiet` to Cargo, for debugging.
+    ///
+    /// Cargo's status lines are shown after the test result and do not take
+    /// part in the comparison against the *.stderr file. Any warnings that
+    /// Cargo prints on its own behalf do.
+    pub fn verbose<P: AsRef<Path>>(&self, path: P) {
+        self.runner
+            .borrow_mut()
+            .verbose
+            .push(path.as_ref().to_owned());
+    }
+
+    /// Fail pass tests whose program leaves behind new files in its working
+    /// directory, for catching generated code that writes stray artifacts.
+    ///
+    /// The working directory is the generated project, or the test case's own
+    /// directory if it is a crate. The new files are listed in the failure.
+    /// Ones in the generated project are removed again so that the next run
+    /// catches them too; nothing is deleted from a test case's directory.
+    ///
+    /// Programs that need scratch space should use `std::env::temp_dir()`.
+    pub fn deny_stray_files(&self) {
+        self.runner.borrow_mut().deny_stray_files = true;
+    }
+
+    /// Build the test cases and the crate under test with the release
+    /// profile, for generated code whose behavior depends on optimizations
+    /// or on overflow checks being disabled.
+    pub fn release(&self) {
+        self.runner.borrow_mut().release = true;
+    }
+
+    /// Add settings to a `[profile.*]` section of the generated Cargo.toml,
+    /// given as TOML.
+    ///
+    /// ```no_run
+    /// # let t = trybuild::TestCases::new();
+    /// t.profile("dev", r#"panic = "abort""#);
+    /// t.profile("release", "lto = true\ncodegen-units = 1");
+    /// ```
+    ///
+    /// Test cases are built with the `dev` profile, or with `release` after
+    /// a call to [`release`][TestCases::release]. The profiles of the
+    /// workspace's Cargo.toml are copied into the generated one first, and
+    /// settings given here replace the same settings from there or from an
+    /// earlier call.
+    pub fn profile<P: AsRef<str>, S: AsRef<str>>(&self, profile: P, settings: S) {
+        self.runner
+            .borrow_mut()
+            .profiles
+            .push((profile.as_ref().to_owned(), settings.as_ref().to_owned()));
+    }
+
+    /// Set the level of a lint or lint group for the test cases, so that they
+    /// are compiled under the same lint regime as code that uses the crate.
+    ///
+    /// ```no_run
+    /// # let t = trybuild::TestCases::new();
+    /// t.lint("unused", "deny");
+    /// t.lint("clippy::pedantic", "warn");
+    /// ```
+    ///
+    /// The level is one of `"allow"`, `"warn"`, `"deny"` or `"forbid"`. Lints
+    /// go in the `[lints]` table of the generated Cargo.toml, under the tool
+    /// that the lint's name starts with, or under `rust` for rustc's own.
+    /// They replace the level of the same lint from the `[lints]` table of
+    /// the crate's Cargo.toml, which is copied into the generated one first.
+    pub fn lint<L: AsRef<str>, S: AsRef<str>>(&self, lint: L, level: S) {
+        self.runner
+            .borrow_mut()
+            .lints
+            .push((lint.as_ref().to_owned(), level.as_ref().to_owned()));
+    }
+
+    /// Enable additional features of one of the crate's dependencies or
+    /// dev-dependencies when building the test cases.
+    ///
+    /// ```no_run
+    /// # let t = trybuild::TestCases::new();
+    /// t.dependency_features("tokio", &["full"]);
+    /// ```
+    ///
+    /// A renamed dependency such as `serde1 = { package = "serde" }` can be
+    /// referred to either as `serde1` or as `serde`.
+    pub fn dependency_features<I, S>(&self, dependency: &str, features: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let features = features
+            .into_iter()
+            .map(|feature| feature.as_ref().to_owned())
+            .collect();
+        self.runner
+            .borrow_mut()
+            .dependency_features
+            .push((dependency.to_owned(), features));
+    }
+
+    /// Turn the default features of one of the crate's dependencies or
+    /// dev-dependencies on or off when building the test cases, regardless
+    /// of what Cargo.toml says.
+    pub fn dependency_default_features(&self, dependency: &str, enabled: bool) {
+        self.runner
+            .borrow_mut()
+            .dependency_default_features
+            .push((dependency.to_owned(), enabled));
+    }
+
+    /// Build the test cases with the given features of the crate enabled, as
+    /// with `cargo build --features`, instead of the ones enabled for the
+    /// `cargo test` invocation running them.
+    ///
+    /// Default features are enabled too, unless
+    /// [`no_default_features`][TestCases::no_default_features] is used. Can be
+    /// called more than once.
+    pub fn features<I, S>(&self, features: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut runner = self.runner.borrow_mut();
+        let selected = runner.features.get_or_insert_with(Vec::new);
+        selected.extend(
+            features
+                .into_iter()
+                .map(|feature| feature.as_ref().to_owned()),
+        );
+    }
+
+    /// Build the test cases with the crate's default features only, rather
+    /// than the features enabled for the `cargo test` invocation running
+    /// them.
+    ///
+    /// Normally trybuild finds out which features of the crate are enabled in
+    /// the test binary it is running in and enables the same ones. That way
+    /// `cargo test --features ...` applies to the test cases too.
+    pub fn no_auto_features(&self) {
+        self.runner.borrow_mut().auto_features = false;
+    }
+
+    /// Build the test cases with every feature of the crate enabled, as with
+    /// `cargo build --all-features`. Takes precedence over
+    /// [`features`][TestCases::features].
+    pub fn all_features(&self) {
+        self.runner.borrow_mut().all_features = true;
+    }
+
+    /// Build the test cases without the crate's default features, as with
+    /// `cargo build --no-default-features`, to check the diagnostics that
+    /// users of the crate's minimal configuration see.
+    ///
+    /// Only features given to [`features`][TestCases::features] are enabled.
+    /// This also overrides
+    /// [`crate_default_features`][TestCases::crate_default_features].
+    pub fn no_default_features(&self) {
+        self.runner.borrow_mut().default_features = false;
+    }
+
+    /// Add a combination of the crate's features to run the test cases
+    /// with. Once any combination is given, the whole suite runs once per
+    /// combination, each with those features enabled in addition to the ones
+    /// given to [`features`][TestCases::features].
+    ///
+    /// A compile_fail test checks its output against a snapshot named after
+    /// the combination if one exists, for example *foo.serde.stderr* for
+    /// `["serde"]` or *foo.serde+std.stderr* for `["serde", "std"]`, falling
+    /// back to *foo.stderr* otherwise. With `TRYBUILD=overwrite`, output that
+    /// matches neither is written to the snapshot named after the
+    /// combination, leaving *foo.stderr* to the others.
+    ///
+    /// ```no_run
+    /// # let t = trybuild::TestCases::new();
+    /// t.feature_set(&[] as &[&str]);
+    /// t.feature_set(&["serde"]);
+    /// t.compile_fail("tests/ui/*.rs");
+    /// ```
+    pub fn feature_set<I, S>(&self, features: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.runner.borrow_mut().feature_sets.push(
+            features
+                .into_iter()
+                .map(|feature| feature.as_ref().to_owned())
+                .collect(),
+        );
+    }
+
+    /// Always enable the default features of the crate under test.
+    ///
+    /// By default the test cases see the crate with the same features that
+    /// are enabled for the `cargo test` invocation running them, and no
+    /// others.
+    pub fn crate_default_features(&self) {
+        self.runner.borrow_mut().crate_default_features = true;
+    }
+
+    /// Always enable the given features of the crate under test, in addition
+    /// to the ones enabled for the `cargo test` invocation running the tests.
+    pub fn crate_features<I, S>(&self, features: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.runner.borrow_mut().crate_features.extend(
+            features
+                .into_iter()
+                .map(|feature| feature.as_ref().to_owned()),
+        );
+    }
+
+    /// Leave the crate's dev-dependencies out of the generated project.
+    ///
+    /// By default the test cases can use every dependency and dev-dependency
+    /// of the crate. Building dev-dependencies that no test case needs costs
+    /// time, and their features are unified with those of the crate's other
+    /// dependencies.
+    pub fn no_dev_dependencies(&self) {
+        self.runner.borrow_mut().dev_dependencies = Some(Vec::new());
+    }
+
+    /// Include only the given dev-dependencies of the crate in the generated
+    /// project, leaving out the rest. Can be called more than once.
+    pub fn dev_dependencies<I, S>(&self, names: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut runner = self.runner.borrow_mut();
+        let allowed = runner.dev_dependencies.get_or_insert_with(Vec::new);
+        allowed.extend(names.into_iter().map(|name| name.as_ref().to_owned()));
+    }
+
+    /// Give the generated project a build script, for test cases that use
+    /// `env!("OUT_DIR")` or depend on what a build script tells Cargo. The
+    /// path is relative to the crate's directory.
+    pub fn build_script<P: AsRef<Path>>(&self, path: P) {
+        self.runner.borrow_mut().build_script = Some(BuildScript::Path(path.as_ref().to_owned()));
+    }
+
+    /// Give the generated project a build script with the given source code.
+    ///
+    /// ```no_run
+    /// # let t = trybuild::TestCases::new();
+    /// t.build_script_source(r#"
+    ///     fn main() {
+    ///         println!("cargo:rustc-env=GREETING=hello");
+    ///     }
+    /// "#);
+    /// ```
+    pub fn build_script_source<S: AsRef<str>>(&self, source: S) {
+        self.runner.borrow_mut().build_script =
+            Some(BuildScript::Source(source.as_ref().to_owned()));
+    }
+
+    /// Add entries to the `[build-dependencies]` of the generated project,
+    /// given as TOML. Paths are relative to the crate's directory.
+    ///
+    /// ```no_run
+    /// # let t = trybuild::TestCases::new();
+    /// t.build_dependencies(r#"cc = "1.0""#);
+    /// ```
+    pub fn build_dependencies<S: AsRef<str>>(&self, dependencies: S) {
+        self.runner
+            .borrow_mut()
+            .build_dependencies
+            .push(dependencies.as_ref().to_owned());
+    }
+
+    /// Build with `cargo --timings=json` and print how long each compilation
+    /// unit took after the test cases have run, to see whether the test cases
+    /// or the dependencies dominate the time spent on the ui tests.
+    ///
+    /// The json format needs a nightly Cargo that still has it. With any other
+    /// Cargo the build runs with plain `--timings` and the times are read from
+    /// the HTML report instead, as far as its layout can be understood.
+    pub fn timings(&self) {
+        self.runner.borrow_mut().timings = true;
+    }
+
+    /// Limit the number of parallel jobs of every cargo invocation, as with
+    /// `cargo build --jobs`.
+    ///
+    /// Useful when several test binaries run their ui tests at the same time,
+    /// such as under cargo-nextest, so that they do not each occupy every CPU.
+    /// The `TRYBUILD_JOBS` environment variable takes precedence over this
+    /// setting.
+    pub fn jobs(&self, jobs: usize) {
+        self.runner.borrow_mut().jobs = Some(jobs);
+    }
+
+    /// Report every test case as ignored instead of building it.
+    ///
+    /// Meant for environments where the ui tests are too expensive to run,
+    /// such as a quick smoke test in CI. Setting `TRYBUILD_SKIP_ALL=1` in the
+    /// environment has the same effect without changing the code, and
+    /// `TRYBUILD_SKIP_ALL=0` takes precedence over this setting.
+    pub fn skip_all(&self) {
+        self.runner.borrow_mut().skip_all = true;
+    }
+
+    /// Decide in code what happens to the compiler output of compile_fail
+    /// tests, instead of through the `TRYBUILD` environment variable.
+    ///
+    /// ```no_run
+    /// use trybuild::Update;
+    ///
+    /// # let t = trybuild::TestCases::new();
+    /// if std::env::var_os("BLESS").is_some() {
+    ///     t.update(Update::Overwrite);
+    /// }
+    /// ```
+    ///
+    /// The `TRYBUILD` environment variable is ignored once this is set.
+    pub fn update(&self, update: Update) {
+        self.runner.borrow_mut().update = Some(update);
+    }
+
+    /// Run the test cases immediately and return how each of them fared,
+    /// instead of panicking at the end if some of them failed.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), trybuild::Error> {
+    /// # let t = trybuild::TestCases::new();
+    /// let report = t.try_run()?;
+    /// for outcome in report.outcomes() {
+    ///     println!("{}: {:?}", outcome.path().display(), outcome.status());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The compiler output is handled as it would be otherwise, according to
+    /// [`update`][TestCases::update] or the `TRYBUILD` environment variable.
+    /// An error is returned if the test cases cannot be run at all, for
+    /// example because Cargo is not installed.
+    pub fn try_run(self) -> Result<Report, Error> {
+        let mut runner = self.runner.borrow_mut();
+        runner.finished = true;
+        runner.try_execute().map_err(Error::from)
+    }
+
+    /// Run the test cases immediately and write the compiler output of every
+    /// compile_fail test into its *.stderr file, as with `TRYBUILD=overwrite`.
+    ///
+    /// Unlike letting `TestCases` run when it goes out of scope, this does not
+    /// panic if some test cases fail. Like [`try_run`][TestCases::try_run],
+    /// it returns an error if the test cases cannot be run at all.
+    pub fn record(self) -> Result<Report, Error> {
+        self.run_with(Update::Overwrite)
+    }
+
+    /// Run the test cases immediately without writing any *.stderr files.
+    ///
+    /// A compile_fail test without a corresponding *.stderr file is counted
+    /// as a failure instead of having its output written to the *wip*
+    /// directory. Like [`record`][TestCases::record], this does not panic if
+    /// some test cases fail.
+    pub fn verify(self) -> Result<Report, Error> {
+        self.run_with(Update::Check)
+    }
+
+    fn run_with(self, update: Update) -> Result<Report, Error> {
+        self.runner.borrow_mut().update = Some(update);
+        self.try_run()
+    }
+}
+
+/// Outcome of running the test cases through [`TestCases::try_run`],
+/// [`TestCases::record`] or [`TestCases::verify`].
+#[derive(Debug)]
+pub struct Report {
+    tests: usize,
+    failures: usize,
+    ignored: usize,
+    outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    /// Number of test cases that were run.
+    pub fn tests(&self) -> usize {
+        self.tests
+    }
+
+    /// Number of test cases that failed.
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// Number of test cases that were skipped, as with
+    /// [`skip_all`][TestCases::skip_all].
+    pub fn ignored(&self) -> usize {
+        self.ignored
+    }
+
+    /// Whether every test case succeeded.
+    pub fn is_success(&self) -> bool {
+        self.failures == 0
+    }
+
+    /// Every test case in the order they ran. A test case that is built with
+    /// more than one toolchain or feature set appears once for each.
+    pub fn outcomes(&self) -> &[Outcome] {
+        &self.outcomes
+    }
+}
+
+/// How a single test case fared, as part of a [`Report`].
+#[derive(Debug)]
+pub struct Outcome {
+    path: PathBuf,
+    label: Option<String>,
+    status: Status,
+    duration: Duration,
+    reason: Option<String>,
+    error: Option<Error>,
+}
+
+/// Whether a test case passed, as part of an [`Outcome`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Status {
+    /// The test case behaved as expected. This includes a compile_fail test
+    /// whose output was written to *wip* or to its *.stderr file.
+    Passed,
+    /// The test case did not behave as expected.
+    Failed,
+    /// The test case was not built, as with
+    /// [`skip_all`][TestCases::skip_all] or a `// trybuild-rust-version:`
+    /// newer than the compiler.
+    Ignored,
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+
+impl Outcome {
+    /// Path of the test case, as given to [`TestCases::pass`] or
+    /// [`TestCases::compile_fail`] after expanding globs.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The toolchain and feature set that the test case was built with, when
+    /// there is more than one, in the same form as printed next to the test.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_ref().map(String::as_str)
+    }
+
+    /// Whether the test case passed, failed, or was not built.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Time spent checking this test case, not counting the shared build of
+    /// the crate under test and its dependencies.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// A few words on why the test case failed.
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_ref().map(String::as_str)
+    }
+
+    /// The error that made the test case fail.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// For a compile_fail test whose output differs from its *.stderr file,
+    /// the normalized contents of the *.stderr file.
+    pub fn expected_output(&self) -> Option<&str> {
+        self.mismatch().map(|mismatch| mismatch.expected.as_str())
+    }
+
+    /// For a compile_fail test whose output differs from its *.stderr file,
+    /// the normalized compiler output.
+    pub fn actual_output(&self) -> Option<&str> {
+        self.mismatch().map(|mismatch| mismatch.actual.as_str())
+    }
+
+    fn mismatch(&self) -> Option<&error::Mismatch> {
+        match self.error.as_ref().map(|error| &error.inner) {
+            Some(error::Error::Mismatch(mismatch)) => Some(mismatch),
+            _ => None,
+        }
+    }
+}
+
+/// Error from [`TestCases::try_run`], or the reason a single test case failed
+/// as part of an [`Outcome`].
+///
+/// The underlying cause, such as an I/O error, is available from
+/// [`source`][std::error::Error::source].
+#[derive(Debug)]
+pub struct Error {
+    inner: error::Error,
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        self.inner.kind()
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Display::fmt(&self.inner, f)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl From<error::Error> for Error {
+    fn from(inner: error::Error) -> Self {
+        Error { inner }
+    }
 }
 
 #[doc(hidden)]
 impl Drop for TestCases {
     fn drop(&mut self) {
-        if !thread::panicking() {
-            self.runner.borrow_mut().run();
+        let mut runner = self.runner.borrow_mut();
+        if !thread::panicking() && !runner.finished {
+            runner.run();
         }
     }
 }
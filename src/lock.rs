@@ -0,0 +1,43 @@
+// Several TestCases may run at the same time, from different #[test] functions
+// of one test binary or from different test binaries, and those of the same
+// crate generate their project in the same directory. Holding this lock while
+// writing the project and building and running the test cases in it makes them
+// take turns rather than overwrite each other's files halfway through a build.
+// Whichever goes first builds the dependencies, which are then up to date for
+// the others.
+
+use fs2::FileExt;
+use std::fs::{self, File};
+use std::path::Path;
+
+use crate::error::Result;
+use crate::message;
+
+#[derive(Debug)]
+pub struct Lock {
+    file: File,
+}
+
+impl Lock {
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(".trybuild-lock");
+        let file = File::create(&path)?;
+        if let Err(err) = file.try_lock_exclusive() {
+            if err.kind() != fs2::lock_contended_error().kind() {
+                return Err(err.into());
+            }
+            message::waiting_for_lock(&path);
+            file.lock_exclusive()?;
+        }
+        Ok(Lock { file })
+    }
+}
+
+// The operating system also releases the lock if the process is killed.
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // Not File::unlock, which std only has since Rust 1.89.
+        let _ = FileExt::unlock(&self.file);
+    }
+}
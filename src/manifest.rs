@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap as Map;
 use std::ffi::OsStr;
 use std::path::PathBuf;
+use toml::value::{Table, Value};
 
 #[derive(Serialize, Debug)]
 pub struct Manifest {
@@ -10,6 +11,10 @@ pub struct Manifest {
     #[serde(skip_serializing_if = "Map::is_empty")]
     pub features: Map<String, Vec<String>>,
     pub dependencies: Map<String, Dependency>,
+    #[serde(rename = "build-dependencies", skip_serializing_if = "Map::is_empty")]
+    pub build_dependencies: Map<String, Dependency>,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub target: Map<String, Target>,
     #[serde(rename = "bin")]
     pub bins: Vec<Bin>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -18,6 +23,22 @@ pub struct Manifest {
     pub patch: Map<String, RegistryPatch>,
     #[serde(skip_serializing_if = "Map::is_empty")]
     pub replace: Map<String, Patch>,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub profile: Map<String, Table>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lints: Option<Map<String, Value>>,
+}
+
+// A package whose only target is a library: either a helper crate that test
+// cases declaring `// trybuild-aux-build: helper.rs` depend on, or a test case
+// that is a proc-macro crate.
+#[derive(Serialize, Debug)]
+pub struct LibManifest {
+    pub package: Package,
+    pub lib: Lib,
+    pub dependencies: Map<String, Dependency>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lints: Option<Map<String, Value>>,
 }
 
 #[derive(Serialize, Debug)]
@@ -26,20 +47,42 @@ pub struct Package {
     pub version: String,
     pub edition: Edition,
     pub publish: bool,
+    #[serde(rename = "rust-version", skip_serializing_if = "Option::is_none")]
+    pub rust_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<PathBuf>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum Edition {
     #[serde(rename = "2015")]
     E2015,
     #[serde(rename = "2018")]
     E2018,
+    #[serde(rename = "2021")]
+    E2021,
+    #[serde(rename = "2024")]
+    E2024,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Target {
+    pub dependencies: Map<String, Dependency>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Lib {
+    pub path: PathBuf,
+    #[serde(rename = "proc-macro", skip_serializing_if = "std::ops::Not::not")]
+    pub proc_macro: bool,
 }
 
 #[derive(Serialize, Debug)]
 pub struct Bin {
     pub name: Name,
     pub path: PathBuf,
+    #[serde(flatten)]
+    pub rest: Table,
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -48,15 +91,22 @@ pub struct Name(pub String);
 #[derive(Serialize, Debug)]
 pub struct Config {
     pub build: Build,
+    #[serde(flatten)]
+    pub rest: Table,
 }
 
 #[derive(Serialize, Debug)]
 pub struct Build {
-    pub rustflags: Vec<&'static str>,
+    pub rustflags: Vec<String>,
 }
 
 #[derive(Serialize, Debug)]
-pub struct Workspace {}
+pub struct Workspace {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolver: Option<String>,
+}
 
 impl Default for Edition {
     fn default() -> Self {
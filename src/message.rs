@@ -5,9 +5,10 @@ use crate::diff::{Diff, Render};
 use crate::error::Error;
 use crate::normalize;
 use crate::term;
+use crate::timings::Unit;
 
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Output;
 
 pub(crate) enum Level {
@@ -17,7 +18,7 @@ pub(crate) enum Level {
 
 pub(crate) use self::Level::*;
 
-pub(crate) fn prepare_fail(err: Error) {
+pub(crate) fn prepare_fail(err: &Error) {
     if err.already_printed() {
         return;
     }
@@ -29,7 +30,7 @@ pub(crate) fn prepare_fail(err: Error) {
     println!();
 }
 
-pub(crate) fn test_fail(err: Error) {
+pub(crate) fn test_fail(err: &Error) {
     if err.already_printed() {
         return;
     }
@@ -42,6 +43,16 @@ pub(crate) fn test_fail(err: Error) {
     println!();
 }
 
+pub(crate) fn waiting_for_lock(path: &Path) {
+    term::bold_color(Yellow);
+    print!("NOTE");
+    term::reset();
+    println!(
+        ": waiting for another test to finish with `{}`.",
+        path.parent().unwrap_or(path).display(),
+    );
+}
+
 pub(crate) fn no_tests_enabled() {
     term::color(Yellow);
     println!("There are no trybuild tests enabled yet.");
@@ -54,7 +65,13 @@ pub(crate) fn ok() {
     term::reset();
 }
 
-pub(crate) fn begin_test(test: &Test, show_expected: bool) {
+pub(crate) fn ignored() {
+    term::color(Yellow);
+    println!("ignored");
+    term::reset();
+}
+
+pub(crate) fn begin_test(test: &Test, show_expected: bool, label: Option<&str>) {
     let display_name = if show_expected {
         test.path
             .file_name()
@@ -63,6 +80,7 @@ pub(crate) fn begin_test(test: &Test, show_expected: bool) {
     } else {
         test.path.as_os_str().to_string_lossy()
     };
+    let display_name = term::hyperlink(&test.path, &display_name);
 
     print!("test ");
     term::bold();
@@ -76,6 +94,10 @@ pub(crate) fn begin_test(test: &Test, show_expected: bool) {
         }
     }
 
+    if let Some(label) = label {
+        print!(" [{}]", label);
+    }
+
     print!(" ... ");
 }
 
@@ -96,7 +118,7 @@ pub(crate) fn should_not_have_compiled() {
 }
 
 pub(crate) fn write_stderr_wip(wip_path: &Path, stderr_path: &Path, stderr: &str) {
-    let wip_path = wip_path.to_string_lossy();
+    let wip_path = term::hyperlink(wip_path, &wip_path.to_string_lossy());
     let stderr_path = stderr_path.to_string_lossy();
 
     term::bold_color(Yellow);
@@ -113,8 +135,36 @@ pub(crate) fn write_stderr_wip(wip_path: &Path, stderr_path: &Path, stderr: &str
     println!();
 }
 
+pub(crate) fn missing_stderr(stderr_path: &Path, stderr: &str) {
+    let stderr_path = term::hyperlink(stderr_path, &stderr_path.to_string_lossy());
+
+    term::bold_color(Red);
+    println!("error");
+    term::color(Red);
+    println!("Expected output file `{}` does not exist.", stderr_path);
+    term::reset();
+    println!();
+    term::bold_color(Red);
+    println!("ACTUAL OUTPUT:");
+    snippet(Red, stderr);
+    println!();
+}
+
+pub(crate) fn malformed_stderr(stderr_path: &Path, problems: &[&str]) {
+    let stderr_path = term::hyperlink(stderr_path, &stderr_path.to_string_lossy());
+
+    term::bold_color(Red);
+    println!("error");
+    term::color(Red);
+    for problem in problems {
+        println!("Expected output file `{}` {}.", stderr_path, problem);
+    }
+    term::reset();
+    println!();
+}
+
 pub(crate) fn overwrite_stderr(stderr_path: &Path, stderr: &str) {
-    let stderr_path = stderr_path.to_string_lossy();
+    let stderr_path = term::hyperlink(stderr_path, &stderr_path.to_string_lossy());
 
     term::bold_color(Yellow);
     println!("wip");
@@ -153,6 +203,21 @@ pub(crate) fn mismatch(expected: &str, actual: &str) {
     println!();
 }
 
+pub(crate) fn wrote_combined_diff(diff_path: &Path, count: usize) {
+    let diff_path = diff_path.to_string_lossy();
+
+    term::bold_color(Yellow);
+    print!("NOTE");
+    term::reset();
+    println!(
+        ": wrote the combined diff of {} mismatched test {} to `{}`.",
+        count,
+        if count == 1 { "case" } else { "cases" },
+        diff_path,
+    );
+    println!();
+}
+
 pub(crate) fn output(warnings: &str, output: &Output) {
     let success = output.status.success();
     let stdout = normalize::trim(&output.stdout);
@@ -191,6 +256,127 @@ pub(crate) fn output(warnings: &str, output: &Output) {
     }
 }
 
+pub(crate) fn cargo_status(status_lines: &str) {
+    if status_lines.is_empty() {
+        return;
+    }
+
+    term::bold_color(Blue);
+    println!("CARGO:");
+    snippet(Blue, status_lines);
+    println!();
+}
+
+pub(crate) fn failure_summary(failed: &[(Test, String)]) {
+    if failed.is_empty() {
+        return;
+    }
+
+    term::bold_color(Red);
+    println!("FAILED TESTS:");
+    term::reset();
+    for (test, reason) in failed {
+        let expected = match test.expected {
+            Expected::Pass => "should pass",
+            Expected::CompileFail => "should fail to compile",
+        };
+        let path = term::hyperlink(&test.path, &test.path.to_string_lossy());
+        println!("    {} [{}]: {}", path, expected, reason);
+    }
+    println!();
+}
+
+// Under GitHub Actions, workflow commands like the following make the failures
+// show up on the affected files in the pull request's diff:
+//
+//     ::error file=tests/ui/foo.rs,title=trybuild test failed::mismatch
+//
+// That is `::error {properties}::{message}`.
+pub(crate) fn github_annotations(failed: &[(Test, String)]) {
+    if env::var_os("GITHUB_ACTIONS").map_or(true, |var| var != "true") {
+        return;
+    }
+
+    // Paths in annotations are relative to the root of the repository.
+    let manifest_dir = env::var_os("CARGO_MANIFEST_DIR").map_or_else(PathBuf::new, PathBuf::from);
+    let github_workspace = env::var_os("GITHUB_WORKSPACE").map(PathBuf::from);
+    for (test, reason) in failed {
+        let path = manifest_dir.join(&test.path);
+        let path = github_workspace
+            .as_ref()
+            .and_then(|workspace| path.strip_prefix(workspace).ok())
+            .unwrap_or(&test.path);
+        println!(
+            "{}",
+            annotation(&path.to_string_lossy(), "trybuild test failed", reason),
+        );
+    }
+}
+
+fn annotation(file: &str, title: &str, message: &str) -> String {
+    format!(
+        "::error file={},title={}::{}",
+        escape_property(file),
+        escape_property(title),
+        escape_data(message),
+    )
+}
+
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[test]
+fn test_annotation() {
+    assert_eq!(
+        annotation(
+            "tests/ui/a,b:c%.rs",
+            "trybuild test failed",
+            "50% done:\nmore"
+        ),
+        "::error file=tests/ui/a%2Cb%3Ac%25.rs,title=trybuild test failed::50%25 done:%0Amore",
+    );
+}
+
+pub(crate) fn timings(units: &[Unit]) {
+    const SHOWN: usize = 10;
+
+    if units.is_empty() {
+        return;
+    }
+
+    term::bold();
+    println!("COMPILE TIMES:");
+    term::reset();
+    for unit in units.iter().take(SHOWN) {
+        println!("{:>9.2}s  {}", unit.duration, unit.name);
+    }
+    if units.len() > SHOWN {
+        println!("{:>10}  ... and {} more", "", units.len() - SHOWN);
+    }
+
+    let total = |test_case: bool| -> f64 {
+        units
+            .iter()
+            .filter(|unit| unit.test_case == test_case)
+            .map(|unit| unit.duration)
+            .sum()
+    };
+    println!(
+        "test cases: {:.2}s, dependencies: {:.2}s",
+        total(true),
+        total(false),
+    );
+    println!();
+}
+
 pub(crate) fn fail_output(level: Level, stdout: &[u8]) {
     let color = match level {
         Fail => Red,
@@ -216,37 +402,170 @@ pub(crate) fn warnings(warnings: &str) {
     println!();
 }
 
+// Lines wider than the terminal are cut off rather than wrapped, so that the
+// structure of compiler output stays recognizable. Only visible characters
+// count: the escape sequences of colored compiler output are passed through
+// whole, including those after the cut, so that colors are still reset.
+struct Truncate {
+    width: usize,
+    // Number of visible characters in each line of the text being printed.
+    line_widths: Vec<usize>,
+    line: usize,
+    column: usize,
+}
+
+impl Truncate {
+    fn new(text: &str, width: usize) -> Self {
+        Truncate {
+            width,
+            line_widths: text.split('\n').map(visible_width).collect(),
+            line: 0,
+            column: 0,
+        }
+    }
+
+    // The part of the next piece of the text that fits, for text that is not
+    // printed all at once.
+    fn fit(&mut self, piece: &str) -> String {
+        let mut fitted = String::new();
+        let mut rest = piece;
+        while let Some(ch) = rest.chars().next() {
+            if let Some(len) = escape_len(rest) {
+                fitted += &rest[..len];
+                rest = &rest[len..];
+                continue;
+            }
+            rest = &rest[ch.len_utf8()..];
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 0;
+                fitted.push(ch);
+                continue;
+            }
+            let line_width = self.line_widths.get(self.line).cloned().unwrap_or(0);
+            self.column += 1;
+            if line_width <= self.width || self.column < self.width {
+                fitted.push(ch);
+            } else if self.column == self.width {
+                fitted.push('…');
+            }
+        }
+        fitted
+    }
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    Truncate::new(line, width).fit(line)
+}
+
+fn visible_width(line: &str) -> usize {
+    let mut width = 0;
+    let mut rest = line;
+    while let Some(ch) = rest.chars().next() {
+        match escape_len(rest) {
+            Some(len) => rest = &rest[len..],
+            None => {
+                width += 1;
+                rest = &rest[ch.len_utf8()..];
+            }
+        }
+    }
+    width
+}
+
+// Length of the escape sequence that `text` starts with, if any: either a
+// control sequence such as a color, or an operating system command such as a
+// hyperlink.
+fn escape_len(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.first() != Some(&0x1b) {
+        return None;
+    }
+    let end = match bytes.get(1) {
+        Some(b'[') => bytes[2..]
+            .iter()
+            .position(|byte| (0x40..=0x7e).contains(byte))
+            .map(|pos| 2 + pos + 1),
+        Some(b']') => bytes[2..]
+            .windows(2)
+            .position(|pair| pair[0] == 0x07 || pair == b"\x1b\\")
+            .map(|pos| {
+                if bytes[2 + pos] == 0x07 {
+                    2 + pos + 1
+                } else {
+                    2 + pos + 2
+                }
+            }),
+        Some(_) => Some(2),
+        None => Some(1),
+    };
+    Some(end.unwrap_or(bytes.len()))
+}
+
 fn snippet(color: Color, content: &str) {
     snippet_diff(color, content, None);
 }
 
 fn snippet_diff(color: Color, content: &str, diff: Option<&Diff>) {
-    fn dotted_line() {
-        println!("{}", "┈".repeat(60));
-    }
+    let width = term::width();
+    let dotted_line = || {
+        println!("{}", "┈".repeat(width.map_or(60, |width| width.min(60))));
+    };
 
     term::color(color);
     dotted_line();
 
     match diff {
         Some(diff) => {
+            let mut truncate = width.map(|width| Truncate::new(content, width));
+            let mut fit = |s: &str| match &mut truncate {
+                Some(truncate) => truncate.fit(s),
+                None => s.to_owned(),
+            };
             for chunk in diff.iter(content) {
                 match chunk {
                     Render::Common(s) => {
                         term::color(color);
-                        print!("{}", s);
+                        print!("{}", fit(s));
                     }
                     Render::Unique(s) => {
                         term::bold_color(color);
-                        print!("\x1B[7m{}", s);
+                        print!("\x1B[7m{}", fit(s));
                     }
                 }
             }
         }
-        None => print!("{}", content),
+        None => match width {
+            Some(width) => {
+                for line in content.lines() {
+                    println!("{}", truncate(line, width));
+                }
+            }
+            None => print!("{}", content),
+        },
     }
 
     term::color(color);
     dotted_line();
     term::reset();
 }
+
+#[test]
+fn test_truncate() {
+    assert_eq!(truncate("abcdef", 6), "abcdef");
+    assert_eq!(truncate("abcdefg", 6), "abcde…");
+    assert_eq!(
+        truncate("\x1b[1m\x1b[38;5;12mabcdef\x1b[0m", 6),
+        "\x1b[1m\x1b[38;5;12mabcdef\x1b[0m",
+    );
+    assert_eq!(
+        truncate("\x1b[1mabc\x1b[0mdefg\x1b[0m", 6),
+        "\x1b[1mabc\x1b[0mde…\x1b[0m",
+    );
+
+    // A line split into pieces, as when printing a diff.
+    let text = "abc|defg\nab|c\n";
+    let mut truncate = Truncate::new(&text.replace('|', ""), 6);
+    let fitted: Vec<String> = text.split('|').map(|piece| truncate.fit(piece)).collect();
+    assert_eq!(fitted, ["abc", "de…\nab", "c\n"]);
+}
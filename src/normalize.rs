@@ -1,10 +1,23 @@
-use std::path::Path;
+use regex::Regex;
+use std::collections::BTreeMap as Map;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 
 #[derive(Copy, Clone)]
 pub struct Context<'a> {
     pub krate: &'a str,
     pub source_dir: &'a Path,
     pub workspace: &'a Path,
+    pub target_spec: Option<&'a Path>,
+    pub roots: &'a [(String, PathBuf)],
+    pub sysroot: Option<&'a Path>,
+    pub custom: &'a [(Regex, String)],
+    // Files and directories in the generated project that the test case was
+    // built from copies of, each with the path of the original.
+    pub copies: &'a [(PathBuf, PathBuf)],
+    // Test case of each bin in the generated project, by bin name.
+    pub bin_paths: &'a Map<String, PathBuf>,
 }
 
 pub fn trim<S: AsRef<[u8]>>(output: S) -> String {
@@ -21,6 +34,106 @@ pub fn trim<S: AsRef<[u8]>>(output: S) -> String {
     normalized
 }
 
+/// Canonicalizes the content of a saved stderr file, returning it together
+/// with a description of each discrepancy that had to be smoothed over.
+pub fn snapshot(content: &str) -> (String, Vec<&'static str>) {
+    let mut problems = Vec::new();
+    let mut content = content;
+
+    if content.starts_with('\u{feff}') {
+        problems.push("starts with a byte order mark");
+        content = &content['\u{feff}'.len_utf8()..];
+    }
+
+    let mut canonical = content.to_owned();
+    if canonical.contains('\r') {
+        problems.push("contains carriage returns");
+        canonical = canonical.replace("\r\n", "\n").replace('\r', "\n");
+    }
+
+    let len = canonical.trim_end_matches('\n').len();
+    if len > 0 {
+        if len == canonical.len() {
+            problems.push("does not end with a newline");
+        } else if len + 1 < canonical.len() {
+            problems.push("ends with blank lines");
+        }
+        canonical.truncate(len);
+        canonical.push('\n');
+    } else {
+        canonical.clear();
+    }
+
+    (canonical, problems)
+}
+
+/// Removes every diagnostic labeled `label`, as in "note" or "help", from
+/// compiler output: subdiagnostics like `= note: ...` as well as top-level
+/// `note: ...` messages together with the source snippet they show.
+pub fn elide(output: &str, label: &str) -> String {
+    let top_level = format!("{}:", label);
+    let attached = format!("= {}:", label);
+
+    let mut elided = String::new();
+    let mut skip_snippet = false;
+    let mut skip_continuation = None;
+    for line in output.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+
+        if skip_snippet {
+            if line.starts_with(char::is_whitespace)
+                || line.starts_with(|ch: char| ch.is_ascii_digit())
+            {
+                continue;
+            }
+            skip_snippet = false;
+        }
+        if let Some(min_indent) = skip_continuation {
+            if indent > min_indent && !trimmed.starts_with(&['=', '|'][..]) {
+                continue;
+            }
+            skip_continuation = None;
+        }
+
+        if line.starts_with(&top_level) {
+            skip_snippet = true;
+            continue;
+        }
+        if trimmed.starts_with(&attached) {
+            skip_continuation = Some(indent);
+            continue;
+        }
+
+        elided += line;
+        elided.push('\n');
+    }
+    elided
+}
+
+/// Removes the lines that only nightly compilers attach to errors about
+/// unstable features:
+///
+/// ```text
+///   = help: add `#![feature(never_type)]` to the crate attributes to enable
+///   = note: this compiler was built on 2024-06-01; consider upgrading it if it is out of date
+/// ```
+pub fn elide_feature_gates(output: &str) -> String {
+    let mut elided = String::new();
+    for line in output.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("= help: add `#![feature(")
+            && trimmed.ends_with("to the crate attributes to enable")
+            || trimmed.starts_with("= note: this compiler was built on ")
+        {
+            continue;
+        }
+        elided += line;
+        elided.push('\n');
+    }
+    elided
+}
+
 /// For a given compiler output, produces the set of saved outputs against which
 /// the compiler's output would be considered correct. If the test's saved
 /// stderr file is identical to any one of these variations, the test will pass.
@@ -36,28 +149,34 @@ pub fn diagnostics(output: Vec<u8>, context: Context) -> Variations {
     let mut from_bytes = String::from_utf8_lossy(&output).to_string();
     from_bytes = from_bytes.replace("\r\n", "\n");
 
-    let variations = [
-        Basic,
-        StripCouldNotCompile,
-        StripCouldNotCompile2,
-        StripForMoreInformation,
-        StripForMoreInformation2,
-        DirBackslash,
-        TrimEnd,
-        RustLib,
-    ]
-    .iter()
-    .map(|normalization| apply(&from_bytes, *normalization, context))
-    .collect();
+    let variations = NORMALIZATIONS
+        .iter()
+        .map(|normalization| apply(&from_bytes, *normalization, context))
+        .collect();
 
     Variations { variations }
 }
 
+/// Normalizes a single message or path taken from rustc's JSON diagnostics
+/// the same way as the preferred variation of rendered compiler output.
+pub fn text(original: &str, context: Context) -> String {
+    let original = original.replace("\r\n", "\n");
+    let latest = *NORMALIZATIONS.last().unwrap();
+    let normalized = apply(&original, latest, context);
+    normalized.trim_end_matches('\n').to_owned()
+}
+
 pub struct Variations {
     variations: Vec<String>,
 }
 
 impl Variations {
+    pub fn single(variation: String) -> Self {
+        Variations {
+            variations: vec![variation],
+        }
+    }
+
     pub fn preferred(&self) -> &str {
         self.variations.last().unwrap()
     }
@@ -77,15 +196,62 @@ enum Normalization {
     DirBackslash,
     TrimEnd,
     RustLib,
+    TargetSpec,
+    DependencySpan,
+    RegistryPath,
+    RustupToolchain,
+    LongTypeFile,
+    StripWarningCount,
+    StripBacktrace,
+    MacroOrigin,
+    TempDir,
+    GitCheckout,
+    BinNames,
+    DependencyVersion,
 }
 
 use self::Normalization::*;
 
+const NORMALIZATIONS: &[Normalization] = &[
+    Basic,
+    StripCouldNotCompile,
+    StripCouldNotCompile2,
+    StripForMoreInformation,
+    StripForMoreInformation2,
+    DirBackslash,
+    TrimEnd,
+    RustLib,
+    TargetSpec,
+    DependencySpan,
+    RegistryPath,
+    RustupToolchain,
+    LongTypeFile,
+    StripWarningCount,
+    StripBacktrace,
+    MacroOrigin,
+    TempDir,
+    GitCheckout,
+    BinNames,
+    DependencyVersion,
+];
+
 fn apply(original: &str, normalization: Normalization, context: Context) -> String {
     let mut normalized = String::new();
+    let mut in_backtrace = false;
 
     for line in original.lines() {
-        if let Some(line) = filter(line, normalization, context) {
+        if normalization >= StripBacktrace {
+            if line == "stack backtrace:" {
+                in_backtrace = true;
+                continue;
+            }
+            if in_backtrace && is_backtrace_frame(line) {
+                continue;
+            }
+            in_backtrace = false;
+        }
+        let line = replace_copies(line, context);
+        if let Some(line) = filter_ansi(&line, normalization, context) {
             normalized += &line;
             if !normalized.ends_with("\n\n") {
                 normalized.push('\n');
@@ -93,9 +259,59 @@ fn apply(original: &str, normalization: Normalization, context: Context) -> Stri
         }
     }
 
+    for (regex, replacement) in context.custom {
+        normalized = regex
+            .replace_all(&normalized, replacement.as_str())
+            .into_owned();
+    }
+
     trim(normalized)
 }
 
+// With TestCases::ansi_snapshots the compiler output contains color escape
+// sequences, which are kept. Whether a line is dropped is decided by its text
+// without them.
+fn filter_ansi(line: &str, normalization: Normalization, context: Context) -> Option<String> {
+    if !line.contains(ESC) {
+        return filter(line, normalization, context);
+    }
+
+    let plain = strip_ansi(line);
+    let filtered = filter(&plain, normalization, context)?;
+    if !plain.trim_start().starts_with("--> ") {
+        return filter(line, normalization, context);
+    }
+
+    // \x1b[1m\x1b[38;5;12m--> \x1b[0m/path/to/tests/ui/x.rs:2:18
+    let mut arrow_end = line.find("--> ")? + 4;
+    while line[arrow_end..].starts_with(ESC) {
+        arrow_end += line[arrow_end..].find('m')? + 1;
+    }
+    let location = &filtered[filtered.find("--> ")? + 4..];
+    Some(line[..arrow_end].to_owned() + location)
+}
+
+const ESC: &str = "\x1b[";
+
+pub fn strip_ansi(line: &str) -> String {
+    let mut plain = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(ESC) {
+        plain += &rest[..start];
+        match rest[start..].find(|ch: char| ch.is_ascii_alphabetic()) {
+            Some(end) => rest = &rest[start + end + 1..],
+            None => {
+                rest = "";
+                break;
+            }
+        }
+    }
+    plain += rest;
+    plain
+}
+
+// Each normalization is checked on its own, even where it only has one rule.
+#[allow(clippy::collapsible_if)]
 fn filter(line: &str, normalization: Normalization, context: Context) -> Option<String> {
     if line.trim_start().starts_with("--> ") {
         if let Some(cut_end) = line.rfind(&['/', '\\'][..]) {
@@ -105,7 +321,7 @@ fn filter(line: &str, normalization: Normalization, context: Context) -> Option<
     }
 
     if line.trim_start().starts_with("::: ") {
-        let mut line = line
+        let mut line = replace_roots(line, context)
             .replace(context.workspace.to_string_lossy().as_ref(), "$WORKSPACE")
             .replace('\\', "/");
         if normalization >= RustLib {
@@ -114,6 +330,17 @@ fn filter(line: &str, normalization: Normalization, context: Context) -> Option<
                 line.replace_range(line.find("::: ").unwrap() + 4..pos + 17, "$RUST");
             }
         }
+        if normalization >= DependencySpan {
+            if let Some(normalized) = dependency_span(&line) {
+                line = normalized;
+            }
+        }
+        if normalization >= RustupToolchain {
+            line = rustup_paths(&line);
+        }
+        if normalization >= GitCheckout {
+            line = git_checkout_paths(&line);
+        }
         return Some(line);
     }
 
@@ -152,8 +379,26 @@ fn filter(line: &str, normalization: Normalization, context: Context) -> Option<
         }
     }
 
+    if normalization >= StripWarningCount {
+        if is_warning_count(line) {
+            return None;
+        }
+    }
+
     let mut line = line.to_owned();
 
+    if normalization >= StripBacktrace {
+        line = hex_addresses(&line);
+    }
+
+    if normalization >= TargetSpec {
+        if let Some(target_spec) = context.target_spec {
+            line = line.replace(target_spec.to_string_lossy().as_ref(), "$TARGET");
+        }
+    }
+
+    line = replace_roots(&line, context);
+
     if normalization >= DirBackslash {
         // https://github.com/dtolnay/trybuild/issues/66
         let source_dir_with_backslash = context.source_dir.to_string_lossy().into_owned() + "\\";
@@ -164,10 +409,650 @@ fn filter(line: &str, normalization: Normalization, context: Context) -> Option<
         line.truncate(line.trim_end().len());
     }
 
+    if normalization >= RegistryPath {
+        line = registry_paths(&line, normalization >= DependencyVersion);
+    }
+
+    if normalization >= RustupToolchain {
+        line = rustup_paths(&line);
+    }
+
+    if normalization >= GitCheckout {
+        line = git_checkout_paths(&line);
+    }
+
+    if normalization >= MacroOrigin {
+        if let Some(normalized) = macro_origin(&line) {
+            line = normalized;
+        }
+    }
+
+    if normalization >= LongTypeFile {
+        if let Some(normalized) = long_type_file(&line) {
+            line = normalized;
+        }
+    }
+
     line = line
         .replace(context.krate, "$CRATE")
         .replace(context.source_dir.to_string_lossy().as_ref(), "$DIR")
         .replace(context.workspace.to_string_lossy().as_ref(), "$WORKSPACE");
 
+    if normalization >= BinNames {
+        // The bins of other test cases, as in Cargo's errors about the
+        // generated project. The test case's own bin is $CRATE by now.
+        line = bin_names(&line, context.bin_paths);
+    }
+
+    if normalization >= TempDir {
+        // Only after $DIR and $WORKSPACE, which may be located in there.
+        let temp_dir = env::temp_dir();
+        let temp_dir = temp_dir.to_string_lossy();
+        let temp_dir = temp_dir.trim_end_matches(&['/', '\\'][..]);
+        if !temp_dir.is_empty() {
+            for separator in &["/", "\\"] {
+                let prefix = format!("{}{}", temp_dir, separator);
+                line = line.replace(&prefix, &format!("$TMP{}", separator));
+            }
+        }
+    }
+
     Some(line)
 }
+
+fn replace_roots(line: &str, context: Context) -> String {
+    let mut line = line.to_owned();
+    if let Some(sysroot) = context.sysroot {
+        line = line.replace(sysroot.to_string_lossy().as_ref(), "$SYSROOT");
+    }
+    for (placeholder, dir) in context.roots {
+        line = line.replace(dir.to_string_lossy().as_ref(), placeholder);
+    }
+    line
+}
+
+// "... for target `trybuild004`" -> "... for target `trybuild004` (tests/ui/x.rs)"
+pub fn bin_names(line: &str, bin_paths: &Map<String, PathBuf>) -> String {
+    const PREFIX: &str = "trybuild";
+
+    let mut translated = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(PREFIX) {
+        let digits = rest[start + PREFIX.len()..]
+            .bytes()
+            .take_while(u8::is_ascii_digit)
+            .count();
+        let end = start + PREFIX.len() + digits;
+        translated += &rest[..end];
+        if let Some(path) = bin_paths.get(&rest[start..end]) {
+            let after = &rest[end..];
+            let quoted = after.starts_with('`');
+            if quoted {
+                translated.push('`');
+            }
+            translated += &format!(" ({})", path.display());
+            rest = if quoted { &after[1..] } else { after };
+        } else {
+            rest = &rest[end..];
+        }
+    }
+    translated += rest;
+    translated
+}
+
+// aux/trybuild003/helper.rs:1:5 -> /path/to/tests/ui/foo/helper.rs:1:5
+//
+// Only where the copied path is not the start of a longer name, such as that
+// of aux/trybuild0031.
+fn replace_copies(line: &str, context: Context) -> String {
+    let mut line = line.to_owned();
+    for (copy, original) in context.copies {
+        let copy = copy.to_string_lossy();
+        let original = original.to_string_lossy();
+        let mut replaced = String::new();
+        let mut rest = line.as_str();
+        while let Some(pos) = rest.find(copy.as_ref()) {
+            let end = pos + copy.len();
+            let continues = rest[end..]
+                .chars()
+                .next()
+                .map_or(false, |ch| ch.is_alphanumeric() || ch == '_');
+            replaced += &rest[..pos];
+            replaced += if continues { &copy } else { &original };
+            rest = &rest[end..];
+        }
+        replaced += rest;
+        line = replaced;
+    }
+    line
+}
+
+// ::: /home/user/.cargo/registry/src/github.com-1ecc6299db9ec823/serde-1.0.104/src/de/mod.rs:531:12
+// ::: $CARGO/serde/src/de/mod.rs
+//
+// The version and the line and column are dropped so that snapshots mentioning
+// a dependency's definitions survive patch releases of that dependency.
+fn dependency_span(line: &str) -> Option<String> {
+    let pos = line.find("/registry/src/")?;
+    let index_and_rest = &line[pos + "/registry/src/".len()..];
+    let rest = &index_and_rest[index_and_rest.find('/')? + 1..];
+    let crate_end = rest.find('/')?;
+    let krate = strip_version(&rest[..crate_end]);
+    let path = strip_position(&rest[crate_end..]);
+    let prefix_end = line.find("::: ")? + 4;
+    Some(format!("{}$CARGO/{}{}", &line[..prefix_end], krate, path))
+}
+
+// note: required by a bound in `/home/user/.cargo/registry/src/index.crates.io-6f17d22bba15001f/serde-1.0.104/src/de/mod.rs`
+// note: required by a bound in `$DEPS/serde-1.0.104/src/de/mod.rs`
+//
+// Same as dependency_span but for paths into the Cargo registry anywhere else
+// in the compiler output, keeping the line and column if there are any. Older
+// snapshots have `$CARGO/serde` here, without the version.
+fn registry_paths(line: &str, versioned: bool) -> String {
+    const REGISTRY: &str = "/registry/src/";
+
+    let mut normalized = String::new();
+    let mut rest = line;
+    while let Some(pos) = rest.find(REGISTRY) {
+        let start = path_start(&rest[..pos]);
+        let index_and_rest = &rest[pos + REGISTRY.len()..];
+        let crate_and_rest = match index_and_rest.find('/') {
+            Some(slash) => &index_and_rest[slash + 1..],
+            None => break,
+        };
+        let crate_end = crate_and_rest.find('/').unwrap_or(crate_and_rest.len());
+        let crate_dir = &crate_and_rest[..crate_end];
+        normalized += &rest[..start];
+        if versioned {
+            normalized += "$DEPS/";
+            normalized += crate_dir;
+        } else {
+            normalized += "$CARGO/";
+            normalized += strip_version(crate_dir);
+        }
+        rest = &crate_and_rest[crate_end..];
+    }
+    normalized += rest;
+    normalized
+}
+
+// /home/user/.cargo/git/checkouts/serde-1a2b3c4d5e6f7a8b/0123abc/serde/src/de/mod.rs
+// $DEPS/serde-1.0.104/src/de/mod.rs
+//
+// The package is found by looking for the nearest Cargo.toml above the file
+// within the checkout, so that a git dependency reads the same as one from the
+// registry. If there is none with a name and version, the path is kept
+// relative to the checkout instead: $DEPS/serde/serde/src/de/mod.rs.
+fn git_checkout_paths(line: &str) -> String {
+    const CHECKOUTS: &str = "/git/checkouts/";
+
+    let mut normalized = String::new();
+    let mut rest = line;
+    while let Some(pos) = rest.find(CHECKOUTS) {
+        let start = path_start(&rest[..pos]);
+        let repo_and_rest = &rest[pos + CHECKOUTS.len()..];
+        let repo_end = match repo_and_rest.find('/') {
+            Some(repo_end) => repo_end,
+            None => break,
+        };
+        let rev_and_rest = &repo_and_rest[repo_end + 1..];
+        let rev_end = rev_and_rest.find('/').unwrap_or(rev_and_rest.len());
+        let checkout_end = pos + CHECKOUTS.len() + repo_end + 1 + rev_end;
+        let checkout = Path::new(&rest[start..checkout_end]);
+        let path = &rev_and_rest[rev_end..];
+        normalized += &rest[..start];
+        normalized += "$DEPS/";
+        rest = match package_in_checkout(checkout, path) {
+            Some((package, path)) => {
+                normalized += &package;
+                path
+            }
+            None => {
+                let repo = &repo_and_rest[..repo_end];
+                // The directory is named after the repository plus a hash of
+                // its URL.
+                normalized += match repo.rfind('-') {
+                    Some(dash) => &repo[..dash],
+                    None => repo,
+                };
+                path
+            }
+        };
+    }
+    normalized += rest;
+    normalized
+}
+
+// Name and version of the innermost package in `checkout` containing `path`,
+// together with the rest of `path` below that package's directory.
+fn package_in_checkout<'a>(checkout: &Path, path: &'a str) -> Option<(String, &'a str)> {
+    let mut end = path.rfind('/')?;
+    loop {
+        let dir = checkout.join(path[..end].trim_start_matches('/'));
+        if let Some(package) = package_id(&dir.join("Cargo.toml")) {
+            return Some((package, &path[end..]));
+        }
+        if end == 0 {
+            return None;
+        }
+        end = path[..end].rfind('/')?;
+    }
+}
+
+fn package_id(manifest_path: &Path) -> Option<String> {
+    let manifest = fs::read_to_string(manifest_path).ok()?;
+    let manifest: toml::Value = toml::from_str(&manifest).ok()?;
+    let package = manifest.get("package")?;
+    let name = package.get("name")?.as_str()?;
+    let version = package.get("version")?.as_str()?;
+    Some(format!("{}-{}", name, version))
+}
+
+// ::: /home/user/.rustup/toolchains/nightly-x86_64-unknown-linux-gnu/lib/rustlib/src/rust/library/core/src/ops/function.rs:250:5
+// ::: $RUST/core/src/ops/function.rs:250:5
+//
+// Paths into any other part of a rustup toolchain become $RUST/ followed by
+// the path within the toolchain, so that they do not depend on the toolchain
+// name or on where rustup is installed.
+fn rustup_paths(line: &str) -> String {
+    const TOOLCHAINS: &str = "/toolchains/";
+
+    let rustup_home = env::var("RUSTUP_HOME").ok();
+    let mut normalized = String::new();
+    let mut rest = line;
+    while let Some(pos) = rest.find(TOOLCHAINS) {
+        let start = path_start(&rest[..pos]);
+        let home = &rest[start..pos];
+        let name_and_rest = &rest[pos + TOOLCHAINS.len()..];
+        let is_rustup = home.ends_with("rustup")
+            || rustup_home
+                .as_ref()
+                .map_or(false, |rustup_home| home == rustup_home);
+        let slash = name_and_rest.find('/');
+        if !is_rustup || slash.is_none() {
+            normalized += &rest[..pos + TOOLCHAINS.len()];
+            rest = name_and_rest;
+            continue;
+        }
+        let mut path = &name_and_rest[slash.unwrap() + 1..];
+        normalized += &rest[..start];
+        normalized += "$RUST/";
+        if path.starts_with("lib/rustlib/src/rust/library/") {
+            path = &path["lib/rustlib/src/rust/library/".len()..];
+        } else if path.starts_with("lib/rustlib/src/rust/") {
+            path = &path["lib/rustlib/src/rust/".len()..];
+        }
+        rest = path;
+    }
+    normalized += rest;
+    normalized
+}
+
+// = note: the full type name has been written to '/home/user/project/target/tests/trybuild/x86_64-unknown-linux-gnu/debug/deps/trybuild001-0f4b2c1d.long-type-11400787226473361049.txt'
+// = note: the full type name has been written to '$OUT_DIR/long-type-$HASH.txt'
+fn long_type_file(line: &str) -> Option<String> {
+    let marker = line.find("has been written to '")?;
+    let start = marker + "has been written to '".len();
+    let len = line[start..].find('\'')?;
+    let path = &line[start..start + len];
+    if !path.contains(".long-type-") || !path.ends_with(".txt") {
+        return None;
+    }
+    Some(format!(
+        "{}$OUT_DIR/long-type-$HASH.txt{}",
+        &line[..start],
+        &line[start + len..],
+    ))
+}
+
+// warning: 2 warnings emitted
+// warning: `trybuild-tests` (bin "trybuild003") generated 1 warning
+fn is_warning_count(line: &str) -> bool {
+    if !line.starts_with("warning: ") {
+        return false;
+    }
+    let rest = line.trim_end();
+    let rest = if rest.ends_with(" emitted") {
+        &rest[..rest.len() - " emitted".len()]
+    } else {
+        rest
+    };
+    let rest = if rest.ends_with(" warnings") {
+        &rest[..rest.len() - " warnings".len()]
+    } else if rest.ends_with(" warning") {
+        &rest[..rest.len() - " warning".len()]
+    } else {
+        return false;
+    };
+    let count_start = rest.rfind(' ').map_or(0, |i| i + 1);
+    let count = &rest[count_start..];
+    let before = &rest[..count_start];
+    !count.is_empty()
+        && count.bytes().all(|b| b.is_ascii_digit())
+        && (before == "warning: " || before.ends_with(" generated "))
+}
+
+// A proc macro that panics while RUST_BACKTRACE is set makes rustc print
+// something like the following, which differs across machines and builds:
+//
+//     stack backtrace:
+//        0: std::panicking::begin_panic
+//                  at /rustc/129f3b9964af4d4a709d1383930ade12dfe7c081/library/std/src/panicking.rs:621:12
+//        1: 0x7f3a2c1d4e5f - <unknown>
+//     note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.
+//
+// All of it is removed, leaving the panic message itself.
+fn is_backtrace_frame(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("at ") || trimmed == "[... omitted frames ...]" {
+        return true;
+    }
+    if trimmed.starts_with("note: Some details are omitted") {
+        return true;
+    }
+    let digits = trimmed.bytes().take_while(u8::is_ascii_digit).count();
+    digits > 0 && trimmed[digits..].starts_with(": ")
+}
+
+// 0x7f3a2c1d4e5f -> 0x$ADDR
+//
+// Only in frames of a backtrace, such as `1: 0x7f3a2c1d4e5f - <unknown>`. A
+// literal like 0xdeadbeef in a snippet of the test case's source is left be.
+fn hex_addresses(line: &str) -> String {
+    const MIN_DIGITS: usize = 8;
+
+    if !is_backtrace_frame(line) && !line.contains(" - <unknown>") {
+        return line.to_owned();
+    }
+
+    let mut normalized = String::new();
+    let mut rest = line;
+    while let Some(pos) = rest.find("0x") {
+        let digits = rest[pos + 2..]
+            .bytes()
+            .take_while(u8::is_ascii_hexdigit)
+            .count();
+        let end = pos + 2 + digits;
+        normalized += &rest[..pos + 2];
+        if digits >= MIN_DIGITS {
+            normalized += "$ADDR";
+        } else {
+            normalized += &rest[pos + 2..end];
+        }
+        rest = &rest[end..];
+    }
+    normalized += rest;
+    normalized
+}
+
+// = note: this error originates in the macro `$crate::format_args_nl` which comes from the expansion of the macro `println` (in Nightly builds, run with -Z macro-backtrace for more info)
+// = note: this error originates in the macro `format_args_nl` which comes from the expansion of the macro `println`
+//
+// The hint about -Z macro-backtrace differs between toolchains, and so does
+// whether macro names are written with the path they were invoked through.
+fn macro_origin(line: &str) -> Option<String> {
+    const HINT: &str = " (in Nightly builds, run with -Z macro-backtrace for more info)";
+
+    let start = line.find("this error originates in ")?;
+    let mut normalized = line[..start].to_owned();
+    let mut rest = &line[start..];
+    if rest.ends_with(HINT) {
+        rest = &rest[..rest.len() - HINT.len()];
+    }
+    while let Some(open) = rest.find('`') {
+        let close = open + 1 + rest[open + 1..].find('`')?;
+        let name = &rest[open + 1..close];
+        let name = name.rsplit("::").next().unwrap_or(name);
+        normalized += &rest[..open + 1];
+        normalized += name;
+        normalized.push('`');
+        rest = &rest[close + 1..];
+    }
+    normalized += rest;
+    Some(normalized)
+}
+
+// Index just past the character that precedes a path ending at the end of
+// `prefix`.
+fn path_start(prefix: &str) -> usize {
+    prefix
+        .rfind(|ch: char| ch.is_whitespace() || "`'\"(".contains(ch))
+        .map_or(0, |i| i + 1)
+}
+
+fn strip_version(crate_dir: &str) -> &str {
+    for (i, _) in crate_dir.match_indices('-') {
+        let version = &crate_dir[i + 1..];
+        let major = version.split('.').next().unwrap();
+        if version.contains('.') && !major.is_empty() && major.bytes().all(|b| b.is_ascii_digit()) {
+            return &crate_dir[..i];
+        }
+    }
+    crate_dir
+}
+
+fn strip_position(path: &str) -> &str {
+    let mut end = path.len();
+    for _ in 0..2 {
+        match path[..end].rfind(':') {
+            Some(colon) if path[colon + 1..end].bytes().all(|b| b.is_ascii_digit()) => {
+                end = colon;
+            }
+            _ => break,
+        }
+    }
+    &path[..end]
+}
+
+#[test]
+fn test_dependency_span() {
+    assert_eq!(
+        dependency_span(
+            "   ::: /home/user/.cargo/registry/src/\
+             github.com-1ecc6299db9ec823/serde-1.0.104/src/de/mod.rs:531:12",
+        )
+        .unwrap(),
+        "   ::: $CARGO/serde/src/de/mod.rs",
+    );
+    assert_eq!(
+        dependency_span(
+            "  ::: /home/user/.cargo/registry/src/\
+             index.crates.io-6f17d22bba15001f/proc-macro2-1.0.86/src/lib.rs",
+        )
+        .unwrap(),
+        "  ::: $CARGO/proc-macro2/src/lib.rs",
+    );
+    assert_eq!(dependency_span("  ::: $DIR/src/lib.rs:1:1"), None);
+}
+
+#[test]
+fn test_registry_paths() {
+    let line = "note: required by a bound in `/home/user/.cargo/registry/src/\
+                index.crates.io-6f17d22bba15001f/serde-1.0.104/src/de/mod.rs:531:12`";
+    assert_eq!(
+        registry_paths(line, true),
+        "note: required by a bound in `$DEPS/serde-1.0.104/src/de/mod.rs:531:12`",
+    );
+    assert_eq!(
+        registry_paths(line, false),
+        "note: required by a bound in `$CARGO/serde/src/de/mod.rs:531:12`",
+    );
+    assert_eq!(registry_paths("no registry here", true), "no registry here");
+}
+
+#[test]
+fn test_rustup_paths() {
+    assert_eq!(
+        rustup_paths(
+            "  ::: /home/user/.rustup/toolchains/nightly-x86_64-unknown-linux-gnu/\
+             lib/rustlib/src/rust/library/core/src/ops/function.rs:250:5",
+        ),
+        "  ::: $RUST/core/src/ops/function.rs:250:5",
+    );
+    assert_eq!(
+        rustup_paths("/opt/toolchains/arm/bin/gcc"),
+        "/opt/toolchains/arm/bin/gcc",
+    );
+}
+
+#[test]
+fn test_is_warning_count() {
+    assert!(is_warning_count("warning: 1 warning emitted"));
+    assert!(is_warning_count("warning: 12 warnings emitted"));
+    assert!(is_warning_count(
+        "warning: `trybuild-tests` (bin \"trybuild003\") generated 2 warnings",
+    ));
+    assert!(!is_warning_count("warning: unused variable: `x`"));
+}
+
+#[test]
+fn test_hex_addresses() {
+    assert_eq!(
+        hex_addresses("1: 0x7f3a2c1d4e5f - <unknown>"),
+        "1: 0x$ADDR - <unknown>",
+    );
+    assert_eq!(
+        hex_addresses("  12: 0x00005581a9b3c4d2 - main"),
+        "  12: 0x$ADDR - main",
+    );
+    assert_eq!(hex_addresses("let x = 0xff;"), "let x = 0xff;");
+    assert_eq!(
+        hex_addresses("2 |     let x: u8 = 0xdeadbeef;"),
+        "2 |     let x: u8 = 0xdeadbeef;",
+    );
+}
+
+#[test]
+fn test_replace_copies() {
+    let copies = [
+        (
+            PathBuf::from("aux/trybuild003/foo.rs"),
+            PathBuf::from("/krate/tests/ui/foo.rs"),
+        ),
+        (
+            PathBuf::from("aux/trybuild003"),
+            PathBuf::from("/krate/tests/ui/foo"),
+        ),
+    ];
+    let context = Context {
+        krate: "trybuild003",
+        source_dir: Path::new("/krate"),
+        workspace: Path::new("/krate"),
+        target_spec: None,
+        roots: &[],
+        sysroot: None,
+        custom: &[],
+        copies: &copies,
+        bin_paths: &Map::new(),
+    };
+    assert_eq!(
+        replace_copies("error: aux/trybuild003/foo.rs:4:1", context),
+        "error: /krate/tests/ui/foo.rs:4:1",
+    );
+    assert_eq!(
+        replace_copies("create file \"aux/trybuild003/missing.rs\"", context),
+        "create file \"/krate/tests/ui/foo/missing.rs\"",
+    );
+    assert_eq!(
+        replace_copies("aux/trybuild0031/foo.rs", context),
+        "aux/trybuild0031/foo.rs",
+    );
+}
+
+#[test]
+fn test_bin_names() {
+    let mut bin_paths = Map::new();
+    bin_paths.insert("trybuild003".to_owned(), PathBuf::from("tests/ui/x.rs"));
+    bin_paths.insert("trybuild004".to_owned(), PathBuf::from("tests/ui/y.rs"));
+    let context = Context {
+        krate: "trybuild003",
+        source_dir: Path::new("/krate"),
+        workspace: Path::new("/krate"),
+        target_spec: None,
+        roots: &[],
+        sysroot: None,
+        custom: &[],
+        copies: &[],
+        bin_paths: &bin_paths,
+    };
+    let output = "error: target `trybuild004` conflicts with `trybuild003`\n";
+    let normalized = diagnostics(output.as_bytes().to_vec(), context);
+    assert_eq!(
+        normalized.preferred(),
+        "error: target `trybuild004` (tests/ui/y.rs) conflicts with `$CRATE`\n",
+    );
+}
+
+#[test]
+fn test_elide() {
+    let output = "\
+error[E0308]: mismatched types
+ --> $DIR/x.rs:2:18
+  |
+2 |     let _: u8 = \"\";
+  |                 ^^ expected `u8`, found `&str`
+  |
+  = note: expected type `u8`
+             found reference `&'static str`
+note: function defined here
+ --> $DIR/x.rs:5:4
+  |
+5 | fn f() {}
+  |    ^
+";
+    let expected = "\
+error[E0308]: mismatched types
+ --> $DIR/x.rs:2:18
+  |
+2 |     let _: u8 = \"\";
+  |                 ^^ expected `u8`, found `&str`
+  |
+";
+    assert_eq!(elide(output, "note"), expected);
+}
+
+#[test]
+fn test_macro_origin() {
+    assert_eq!(
+        macro_origin(
+            "  = note: this error originates in the macro `$crate::format_args_nl` \
+             which comes from the expansion of the macro `println` \
+             (in Nightly builds, run with -Z macro-backtrace for more info)",
+        )
+        .unwrap(),
+        "  = note: this error originates in the macro `format_args_nl` \
+         which comes from the expansion of the macro `println`",
+    );
+}
+
+#[test]
+fn test_strip_ansi() {
+    assert_eq!(
+        strip_ansi("\x1b[0m\x1b[1m\x1b[38;5;9merror\x1b[0m\x1b[0m\x1b[1m: aborting\x1b[0m"),
+        "error: aborting",
+    );
+}
+
+#[test]
+fn test_git_checkout_paths() {
+    let cargo_home = env::temp_dir().join(format!("trybuild-git-{}", std::process::id()));
+    let checkout = cargo_home.join("git/checkouts/serde-1a2b3c4d5e6f7a8b/0123abc");
+    fs::create_dir_all(checkout.join("serde/src/de")).unwrap();
+    fs::write(
+        checkout.join("serde/Cargo.toml"),
+        "[package]\nname = \"serde\"\nversion = \"1.0.104\"\n",
+    )
+    .unwrap();
+    fs::write(checkout.join("Cargo.toml"), "[workspace]\n").unwrap();
+
+    let line = format!("  ::: {}/serde/src/de/mod.rs:531:12", checkout.display());
+    let normalized = git_checkout_paths(&line);
+    let outside = git_checkout_paths(
+        "  ::: /nonexistent/.cargo/git/checkouts/serde-1a2b3c4d5e6f7a8b/0123abc/serde/src/de/mod.rs:531:12",
+    );
+    fs::remove_dir_all(&cargo_home).unwrap();
+
+    assert_eq!(normalized, "  ::: $DEPS/serde-1.0.104/src/de/mod.rs:531:12");
+    assert_eq!(outside, "  ::: $DEPS/serde/serde/src/de/mod.rs:531:12");
+}
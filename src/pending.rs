@@ -0,0 +1,50 @@
+// Snapshots that the test harness leaves in the wip directory, whether for a
+// test case without a *.stderr file or for one whose output no longer matches,
+// are listed in wip/pending.json along with the *.stderr file each of them is
+// meant for. That is what lets `cargo trybuild` accept them without rerunning
+// the tests.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const INDEX: &str = "pending.json";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Pending {
+    // File name of the snapshot within the wip directory.
+    pub snapshot: String,
+    // The *.stderr file to move it to, relative to the directory containing
+    // Cargo.toml.
+    pub destination: PathBuf,
+}
+
+pub fn load(wip_dir: &Path) -> Vec<Pending> {
+    let json = match fs::read(wip_dir.join(INDEX)) {
+        Ok(json) => json,
+        Err(_) => return Vec::new(),
+    };
+    serde_json::from_slice(&json).unwrap_or_default()
+}
+
+pub fn save(wip_dir: &Path, pending: &[Pending]) -> io::Result<()> {
+    let path = wip_dir.join(INDEX);
+    if pending.is_empty() {
+        return match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+    let json = serde_json::to_string_pretty(pending)?;
+    fs::write(path, json + "\n")
+}
+
+// Replaces whatever was recorded earlier for the same snapshot or the same
+// destination.
+pub fn record(wip_dir: &Path, entry: Pending) -> io::Result<()> {
+    let mut pending = load(wip_dir);
+    pending.retain(|p| p.snapshot != entry.snapshot && p.destination != entry.destination);
+    pending.push(entry);
+    save(wip_dir, &pending)
+}
@@ -0,0 +1,248 @@
+// `cargo trybuild review` walks through the snapshots waiting in the wip
+// directory one at a time. Each is shown next to the *.stderr file it would
+// replace, and can be accepted into place, rejected, skipped, or edited by hand
+// first in $VISUAL or $EDITOR.
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::process::Command;
+use termcolor::Color::{Blue, Green, Red};
+
+use crate::cli::{self, Crate};
+use crate::diff;
+use crate::pending::{self, Pending};
+use crate::term;
+
+// Terminals narrower than this get a unified diff instead of two columns.
+const MIN_SIDE_BY_SIDE_WIDTH: usize = 80;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Action {
+    Accept,
+    Reject,
+    Edit,
+    Skip,
+    Quit,
+}
+
+pub fn review(krate: &Crate, filters: &[String]) -> io::Result<()> {
+    let mut pending = krate.pending();
+    let total = pending.iter().filter(|p| cli::selected(p, filters)).count();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    let mut i = 0;
+    let mut position = 0;
+    while i < pending.len() {
+        if !cli::selected(&pending[i], filters) {
+            i += 1;
+            continue;
+        }
+        position += 1;
+
+        let action = loop {
+            show(krate, &pending[i], position, total)?;
+            let action = prompt(&mut lines)?;
+            if action != Action::Edit {
+                break action;
+            }
+            edit(krate, &pending[i])?;
+        };
+        println!();
+
+        match action {
+            Action::Accept => {
+                cli::accept(krate, &pending[i])?;
+                pending.remove(i);
+            }
+            Action::Reject => {
+                fs::remove_file(krate.wip_dir.join(&pending[i].snapshot))?;
+                pending.remove(i);
+            }
+            Action::Skip => i += 1,
+            Action::Edit | Action::Quit => break,
+        }
+    }
+
+    pending::save(&krate.wip_dir, &pending)
+}
+
+fn show(krate: &Crate, entry: &Pending, position: usize, total: usize) -> io::Result<()> {
+    let actual = fs::read_to_string(krate.wip_dir.join(&entry.snapshot))?;
+    let expected = fs::read_to_string(krate.dir.join(&entry.destination)).ok();
+
+    term::bold();
+    print!("[{}/{}] {}", position, total, entry.destination.display());
+    term::reset();
+    println!(" ({})", if expected.is_some() { "changed" } else { "new" });
+
+    let expected = expected.unwrap_or_default();
+    let width = term::width().unwrap_or(120);
+    if width >= MIN_SIDE_BY_SIDE_WIDTH {
+        side_by_side(&expected, &actual, width);
+    } else {
+        unified(&expected, &actual);
+    }
+    println!();
+    Ok(())
+}
+
+fn prompt(lines: &mut impl Iterator<Item = io::Result<String>>) -> io::Result<Action> {
+    loop {
+        print!("[a]ccept, [r]eject, [e]dit, [s]kip, [q]uit: ");
+        let line = match lines.next().transpose()? {
+            Some(line) => line,
+            None => {
+                println!();
+                return Ok(Action::Quit);
+            }
+        };
+        match line.trim() {
+            "a" | "accept" | "y" | "yes" => return Ok(Action::Accept),
+            "r" | "reject" | "n" | "no" => return Ok(Action::Reject),
+            "e" | "edit" => return Ok(Action::Edit),
+            "s" | "skip" => return Ok(Action::Skip),
+            "q" | "quit" => return Ok(Action::Quit),
+            _ => {}
+        }
+    }
+}
+
+// The command may come with arguments of its own, as in VISUAL="code --wait".
+fn edit(krate: &Crate, entry: &Pending) -> io::Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program)
+        .args(words)
+        .arg(krate.wip_dir.join(&entry.snapshot))
+        .status()?;
+    if !status.success() {
+        term::bold_color(Red);
+        print!("error");
+        term::reset();
+        println!(": `{}` exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+fn unified(expected: &str, actual: &str) {
+    for line in diff::lines(expected, actual).lines() {
+        match line.chars().next() {
+            Some('-') => term::color(Red),
+            Some('+') => term::color(Green),
+            _ => term::color(Blue),
+        }
+        println!("{}", line);
+    }
+    term::reset();
+}
+
+// Expected output on the left, actual on the right. Removed and added lines
+// between two common ones are paired up row by row, and long lines wrap within
+// their column.
+fn side_by_side(expected: &str, actual: &str, width: usize) {
+    let column = (width - 3) / 2;
+    term::bold();
+    println!("{:<column$} │ {}", "expected", "actual", column = column);
+    term::reset();
+
+    for row in rows(&diff::lines(expected, actual)) {
+        let left = wrap(row.left.unwrap_or(""), column);
+        let right = wrap(row.right.unwrap_or(""), column);
+        for i in 0..left.len().max(right.len()) {
+            let left_part = left.get(i).map_or("", String::as_str);
+            let right_part = right.get(i).map_or("", String::as_str);
+            term::color(if row.changed { Red } else { Blue });
+            print!("{}", left_part);
+            term::reset();
+            print!("{:pad$} │ ", "", pad = column - left_part.chars().count());
+            term::color(if row.changed { Green } else { Blue });
+            println!("{}", right_part);
+            term::reset();
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+struct Row<'a> {
+    left: Option<&'a str>,
+    right: Option<&'a str>,
+    changed: bool,
+}
+
+fn rows(diff: &str) -> Vec<Row<'_>> {
+    fn flush<'a>(rows: &mut Vec<Row<'a>>, removed: &mut Vec<&'a str>, added: &mut Vec<&'a str>) {
+        for i in 0..removed.len().max(added.len()) {
+            rows.push(Row {
+                left: removed.get(i).copied(),
+                right: added.get(i).copied(),
+                changed: true,
+            });
+        }
+        removed.clear();
+        added.clear();
+    }
+
+    let mut rows = Vec::new();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+
+    for line in diff.lines() {
+        let (marker, content) = line.split_at(line.len().min(1));
+        match marker {
+            "-" => removed.push(content),
+            "+" => added.push(content),
+            _ => {
+                flush(&mut rows, &mut removed, &mut added);
+                rows.push(Row {
+                    left: Some(content),
+                    right: Some(content),
+                    changed: false,
+                });
+            }
+        }
+    }
+    flush(&mut rows, &mut removed, &mut added);
+    rows
+}
+
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let chars: Vec<char> = line.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars
+        .chunks(width.max(1))
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
+
+#[test]
+fn test_rows() {
+    let diff = diff::lines("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n");
+    let row = |left, right, changed| Row {
+        left,
+        right,
+        changed,
+    };
+    assert_eq!(
+        rows(&diff),
+        vec![
+            row(Some("a"), Some("a"), false),
+            row(Some("b"), Some("B"), true),
+            row(Some("c"), Some("c"), false),
+            row(Some("d"), Some("d"), false),
+            row(None, Some("e"), true),
+        ],
+    );
+}
+
+#[test]
+fn test_wrap() {
+    assert_eq!(wrap("", 3), vec![""]);
+    assert_eq!(wrap("abcdefg", 3), vec!["abc", "def", "g"]);
+}
@@ -1,68 +1,370 @@
+use regex::Regex;
 use std::collections::BTreeMap as Map;
 use std::env;
 use std::ffi::{OsStr, OsString};
+use std::fmt::Debug;
 use std::fs::{self, File};
+use std::io;
+use std::iter;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use termcolor::ColorChoice;
+use toml::Value;
 
-use super::{Expected, Runner, Test};
+use super::{BuildScript, Expected, Outcome, Report, Runner, Status, Test};
+use crate::archive;
 use crate::cargo;
+use crate::config;
 use crate::dependencies::{self, Dependency};
+use crate::diff;
+use crate::directives::{self, Version};
 use crate::env::Update;
-use crate::error::{Error, Result};
+use crate::error::{Error, Mismatch, Result};
+use crate::examples;
 use crate::features;
-use crate::manifest::{Bin, Build, Config, Manifest, Name, Package, Workspace};
+use crate::hash;
+use crate::json;
+use crate::lock::Lock;
+use crate::manifest::{
+    Bin, Build, Config, Lib, LibManifest, Manifest, Name, Package, Target, Workspace,
+};
 use crate::message::{self, Fail, Warn};
 use crate::normalize::{self, Context, Variations};
+use crate::pending::{self, Pending};
 use crate::rustflags;
+use crate::term;
+use crate::timings::{self, Timings};
+use crate::volatile;
+use crate::warm;
 
 #[derive(Debug)]
 pub struct Project {
     pub dir: PathBuf,
-    source_dir: PathBuf,
-    pub target_dir: PathBuf,
+    // Held for as long as the project is in use.
+    _lock: Lock,
+    pub source_dir: PathBuf,
+    pub cargo_target_dir: PathBuf,
     pub name: String,
     update: Update,
     pub has_pass: bool,
     has_compile_fail: bool,
     pub features: Option<Vec<String>>,
-    workspace: PathBuf,
+    pub all_features: bool,
+    pub workspace: PathBuf,
+    pub target: Option<String>,
+    target_spec: Option<PathBuf>,
+    roots: Vec<(String, PathBuf)>,
+    pub toolchain: Option<String>,
+    channel: Option<String>,
+    // Whether the suite runs with several toolchains, whose output each gets
+    // a snapshot named after its channel when one is written.
+    channel_snapshots: bool,
+    snapshot_suffix: Option<String>,
+    label: Option<String>,
+    pub rustc_wrapper: Option<PathBuf>,
+    pub rustc: Option<PathBuf>,
+    sysroot: Option<PathBuf>,
+    normalizations: Vec<(Regex, String)>,
+    pub offline: bool,
+    pub cargo_args: Vec<String>,
+    pub shared_lockfile: bool,
+    pub bin_paths: Map<String, PathBuf>,
+    strict_snapshots: bool,
+    ignore_notes: bool,
+    ignore_help: bool,
+    ignore_feature_gates: bool,
+    pub json_diagnostics: bool,
+    wip_dir: PathBuf,
+    pub json_snapshots: bool,
+    pub ansi_snapshots: bool,
+    verbose: Vec<glob::Pattern>,
+    deny_stray_files: bool,
+    pub release: bool,
+    pub timings: Option<Timings>,
+    pub jobs: Option<usize>,
+    rustc_version: Option<Version>,
+    pub bindeps: bool,
+    archive: Option<archive::Mode>,
 }
 
 impl Runner {
     pub fn run(&mut self) {
-        let mut tests = expand_globs(&self.tests);
-        filter(&mut tests);
+        let report = self.execute();
+
+        if report.failures > 0 {
+            panic!("{} of {} tests failed", report.failures, report.tests);
+        }
+    }
+
+    pub fn execute(&mut self) -> Report {
+        self.try_execute()
+            .unwrap_or_else(|_| panic!("tests failed"))
+    }
 
-        let project = self.prepare(&tests).unwrap_or_else(|err| {
-            message::prepare_fail(err);
-            panic!("tests failed");
+    // Fails if the test cases cannot be run at all, after printing why.
+    pub fn try_execute(&mut self) -> Result<Report> {
+        let _scope = term::scope(term::Scope {
+            color: self.color.map(|color| {
+                if color {
+                    ColorChoice::Always
+                } else {
+                    ColorChoice::Never
+                }
+            }),
+            max_width: self.max_width,
         });
 
-        print!("\n\n");
+        let skip_all = match crate::env::flag("TRYBUILD_SKIP_ALL", self.skip_all) {
+            Ok(skip_all) => skip_all,
+            Err(err) => {
+                message::prepare_fail(&err);
+                return Err(err);
+            }
+        };
+        if skip_all {
+            return Ok(self.skip());
+        }
+
+        let toolchains = if self.toolchains.is_empty() {
+            vec![None]
+        } else {
+            self.toolchains.iter().map(|t| Some(t.as_str())).collect()
+        };
+        let show_toolchain = toolchains.len() > 1;
+
+        let feature_sets = if self.feature_sets.is_empty() {
+            vec![None]
+        } else {
+            self.feature_sets.iter().map(|set| Some(&set[..])).collect()
+        };
+
+        let mut all_tests = self.tests.clone();
+        match self.doc_examples() {
+            Ok(tests) => all_tests.extend(tests),
+            Err(err) => {
+                message::prepare_fail(&err);
+                return Err(err);
+            }
+        }
+
+        let mut runs = Vec::new();
+        for &toolchain in &toolchains {
+            for &feature_set in &feature_sets {
+                runs.push((toolchain, feature_set));
+            }
+        }
 
-        let len = tests.len();
+        let mut len = 0;
         let mut failures = 0;
+        let mut ignored = 0;
+        let mut mismatches = Vec::new();
+        let mut failed = Vec::new();
+        let mut outcomes = Vec::new();
+
+        for (toolchain, feature_set) in runs {
+            let label = display_label(toolchain.filter(|_| show_toolchain), feature_set);
+
+            let mut tests = expand_globs(&all_tests);
+            filter(&mut tests);
+            load_manifest_snippets(&mut tests);
+            load_aux_build(&mut tests);
+
+            // Test cases whose manifest snippet changes the package, or that
+            // depend on helper crates, are each built in a project of their
+            // own, after all the others.
+            let mut groups = vec![Vec::new()];
+            for test in tests {
+                if test.is_isolated() {
+                    groups.push(vec![test]);
+                } else {
+                    groups[0].push(test);
+                }
+            }
+            if groups[0].is_empty() && groups.len() > 1 {
+                groups.remove(0);
+            }
+
+            for tests in groups {
+                let isolated = tests.iter().any(ExpandedTest::is_isolated);
+                let start = Instant::now();
+                let prepared = {
+                    let _span = span!("prepare", tests = tests.len(), ?toolchain, ?feature_set);
+                    self.prepare(&tests, toolchain, feature_set)
+                };
+                let mut project = match prepared {
+                    Ok(project) => project,
+                    Err(err) if isolated => {
+                        message::begin_test(
+                            &tests[0].test,
+                            false,
+                            label.as_ref().map(String::as_str),
+                        );
+                        let mut outcome =
+                            outcome(&tests[0].test, &label, Status::Failed, start.elapsed());
+                        outcome.reason = Some(err.summary());
+                        failed.push((tests[0].test.clone(), err.summary()));
+                        message::test_fail(&err);
+                        outcome.error = Some(err.into());
+                        outcomes.push(outcome);
+                        len += 1;
+                        failures += 1;
+                        continue;
+                    }
+                    Err(err) => {
+                        message::prepare_fail(&err);
+                        return Err(err);
+                    }
+                };
+                project.label = label.clone();
+                project.channel_snapshots = show_toolchain;
+
+                print!("\n\n");
+
+                len += tests.len();
+
+                if tests.is_empty() {
+                    message::no_tests_enabled();
+                } else {
+                    for test in tests {
+                        if test.is_too_new(&project) || test.is_unreplayable(&project) {
+                            let show_expected = project.has_pass && project.has_compile_fail;
+                            let display_label = project.display_label();
+                            message::begin_test(&test.test, show_expected, display_label);
+                            message::ignored();
+                            let duration = Duration::from_secs(0);
+                            outcomes.push(outcome(&test.test, &label, Status::Ignored, duration));
+                            ignored += 1;
+                            continue;
+                        }
+                        let path = test.test.clone();
+                        let start = Instant::now();
+                        let result = test.run(&project);
+                        let mut outcome = outcome(&path, &label, Status::Passed, start.elapsed());
+                        if let Err(err) = result {
+                            outcome.status = Status::Failed;
+                            outcome.reason = Some(err.summary());
+                            failed.push((path, err.summary()));
+                            failures += 1;
+                            if let Error::Mismatch(mismatch) = &err {
+                                mismatches.push(mismatch.clone());
+                            } else {
+                                message::test_fail(&err);
+                            }
+                            outcome.error = Some(err.into());
+                        }
+                        outcomes.push(outcome);
+                    }
+                }
+
+                if project.timings.is_some() {
+                    message::timings(&timings::summarize(&project));
+                }
+
+                print!("\n\n");
+            }
+        }
+
+        if !mismatches.is_empty() {
+            let wip_dir = self.wip_dir();
+            write_combined_diff(&wip_dir, &mismatches);
+            if let Err(err) = write_pending_mismatches(&wip_dir, &mismatches) {
+                message::test_fail(&err);
+            }
+        }
+
+        message::failure_summary(&failed);
+        message::github_annotations(&failed);
+
+        Ok(Report {
+            tests: len - ignored,
+            failures,
+            ignored,
+            outcomes,
+        })
+    }
+
+    // The current directory of a test binary depends on how Cargo was invoked,
+    // so a relative wip directory is resolved against the directory containing
+    // Cargo.toml instead.
+    fn wip_dir(&self) -> PathBuf {
+        let wip_dir = env::var_os("TRYBUILD_WIP_DIR")
+            .map(PathBuf::from)
+            .or_else(|| self.wip_dir.clone())
+            .unwrap_or_else(|| PathBuf::from("wip"));
+        match env::var_os("CARGO_MANIFEST_DIR") {
+            Some(manifest_dir) => Path::new(&manifest_dir).join(wip_dir),
+            None => wip_dir,
+        }
+    }
+
+    // Features explicitly selected for one run of the test suite.
+    fn selected_features(&self, feature_set: Option<&[String]>) -> Option<Vec<String>> {
+        match (&self.features, feature_set) {
+            (Some(features), Some(set)) => Some(features.iter().chain(set).cloned().collect()),
+            (Some(features), None) => Some(features.clone()),
+            (None, Some(set)) => Some(set.to_vec()),
+            (None, None) => None,
+        }
+    }
+
+    fn skip(&self) -> Report {
+        let mut tests = expand_globs(&self.tests);
+        filter(&mut tests);
+
+        print!("\n\n");
 
         if tests.is_empty() {
             message::no_tests_enabled();
         } else {
-            for test in tests {
-                if let Err(err) = test.run(&project) {
-                    failures += 1;
-                    message::test_fail(err);
-                }
+            for test in &tests {
+                let show_expected = false;
+                message::begin_test(&test.test, show_expected, None);
+                message::ignored();
             }
         }
 
         print!("\n\n");
 
-        if failures > 0 && project.name != "trybuild-tests" {
-            panic!("{} of {} tests failed", failures, len);
+        let label = None;
+        let duration = Duration::from_secs(0);
+        Report {
+            tests: 0,
+            failures: 0,
+            ignored: tests.len(),
+            outcomes: tests
+                .iter()
+                .map(|test| outcome(&test.test, &label, Status::Ignored, duration))
+                .collect(),
         }
     }
 
-    fn prepare(&self, tests: &[ExpandedTest]) -> Result<Project> {
-        let metadata = cargo::metadata()?;
+    // The test cases of doc_examples, written to the target directory.
+    fn doc_examples(&self) -> Result<Vec<Test>> {
+        let mut tests = Vec::new();
+        if self.doc_examples.is_empty() {
+            return Ok(tests);
+        }
+
+        let target_dir = warm::metadata()?.target_directory;
+        let crate_name = env::var("CARGO_PKG_NAME").map_err(Error::PkgName)?;
+        let source_dir = env::var_os("CARGO_MANIFEST_DIR")
+            .map(PathBuf::from)
+            .ok_or(Error::ProjectDir)?;
+        let scratch_dir = examples::scratch_dir(&target_dir, &crate_name);
+        for (source, dir) in &self.doc_examples {
+            let dir = dir.strip_prefix(&source_dir).unwrap_or(dir);
+            tests.extend(examples::extract(source, dir, &scratch_dir)?);
+        }
+        Ok(tests)
+    }
+
+    fn prepare(
+        &self,
+        tests: &[ExpandedTest],
+        toolchain: Option<&str>,
+        feature_set: Option<&[String]>,
+    ) -> Result<Project> {
+        let metadata = warm::metadata()?;
         let target_dir = metadata.target_directory;
         let workspace = metadata.workspace_root;
 
@@ -81,37 +383,334 @@ impl Runner {
             .map(PathBuf::from)
             .ok_or(Error::ProjectDir)?;
 
-        let features = features::find();
+        let selected = self.selected_features(feature_set);
+        let features = match &selected {
+            Some(selected) => {
+                let mut features = selected.clone();
+                if self.default_features {
+                    features.push("default".to_owned());
+                }
+                Some(features)
+            }
+            None if !self.default_features => Some(Vec::new()),
+            None if self.auto_features => features::find(),
+            None => None,
+        };
+
+        let cargo_config = config::load(&source_dir);
+
+        // Build for the same target as `cargo test` would if none is given.
+        let target = self
+            .target
+            .clone()
+            .or_else(|| config::build_target(&cargo_config));
+        let target_spec = target
+            .as_ref()
+            .filter(|target| target.ends_with(".json"))
+            .map(|target| source_dir.join(target));
+        let target = match &target_spec {
+            Some(target_spec) => Some(target_spec.to_string_lossy().into_owned()),
+            None => target,
+        };
+
+        let mut roots = Vec::new();
+        for (name, dir) in &self.roots {
+            let placeholder = format!("${}", name);
+            let dir = source_dir.join(dir);
+            if let Ok(canonical) = dir.canonicalize() {
+                roots.push((placeholder.clone(), canonical));
+            }
+            roots.push((placeholder, dir));
+        }
+        // Vendored dependencies: $DEPS/serde-1.0.104/src/de/mod.rs
+        for dir in config::source_directories(&cargo_config) {
+            roots.push(("$DEPS".to_owned(), dir));
+        }
+        // Replace the most specific directory first in case one root is
+        // nested inside another.
+        roots.sort_by_key(|(_placeholder, dir)| std::cmp::Reverse(dir.as_os_str().len()));
+
+        let rustc = self.rustc.as_ref().map(|rustc| {
+            if rustc.is_relative() && rustc.components().count() > 1 {
+                source_dir.join(rustc)
+            } else {
+                rustc.clone()
+            }
+        });
+        let mut normalizations = Vec::new();
+        for (pattern, replacement) in &self.normalizations {
+            let regex = Regex::new(pattern).map_err(Error::Regex)?;
+            normalizations.push((regex, replacement.clone()));
+        }
+
+        let sysroot = match &rustc {
+            Some(rustc) => Some(cargo::sysroot(rustc)?),
+            None => None,
+        };
+
+        let project_dir = match tests.iter().find(|expanded| expanded.is_isolated()) {
+            Some(expanded) => format!("{}-{}", crate_name, expanded.name.0),
+            None => crate_name.clone(),
+        };
+
+        let dir = path!(target_dir / "tests" / project_dir);
+        let lock = Lock::acquire(&dir)?;
 
         let mut project = Project {
-            dir: path!(target_dir / "tests" / crate_name),
+            dir,
+            _lock: lock,
             source_dir,
-            target_dir,
+            cargo_target_dir: if self.share_target_dir {
+                target_dir.clone()
+            } else {
+                path!(target_dir / "tests" / "target")
+            },
             name: format!("{}-tests", crate_name),
-            update: Update::env()?,
+            update: match self.update {
+                Some(update) => update,
+                None => Update::env()?,
+            },
             has_pass,
             has_compile_fail,
             features,
+            all_features: self.all_features,
             workspace,
+            target,
+            target_spec,
+            roots,
+            toolchain: toolchain.map(str::to_owned),
+            // "nightly-2024-06-01" -> "nightly"
+            channel: toolchain.map(|t| t.split('-').next().unwrap_or(t).to_owned()),
+            channel_snapshots: false,
+            snapshot_suffix: feature_set
+                .filter(|set| !set.is_empty())
+                .map(|set| set.join("+")),
+            label: None,
+            rustc_wrapper: self.rustc_wrapper.clone(),
+            rustc,
+            sysroot,
+            normalizations,
+            offline: crate::env::offline(self.offline)?,
+            cargo_args: self.cargo_args.clone(),
+            shared_lockfile: false,
+            bin_paths: Map::new(),
+            strict_snapshots: self.strict_snapshots,
+            ignore_notes: self.ignore_notes,
+            ignore_help: self.ignore_help,
+            ignore_feature_gates: self.ignore_feature_gates,
+            json_diagnostics: self.json_diagnostics,
+            wip_dir: self.wip_dir(),
+            json_snapshots: self.json_snapshots,
+            ansi_snapshots: self.ansi_snapshots,
+            verbose: self
+                .verbose
+                .iter()
+                .map(|path| glob::Pattern::new(&path.to_string_lossy()))
+                .collect::<std::result::Result<_, _>>()?,
+            deny_stray_files: self.deny_stray_files,
+            release: self.release,
+            timings: if self.timings {
+                Some(Timings::new())
+            } else {
+                None
+            },
+            jobs: crate::env::jobs(self.jobs)?,
+            archive: archive::Mode::env()?,
+            rustc_version: None,
+            bindeps: false,
         };
 
-        let manifest = self.make_manifest(crate_name, &project, tests)?;
-        let manifest_toml = toml::to_string(&manifest)?;
+        if let Some(version) = cargo::rustc_version(&project) {
+            project.rustc_version = directives::parse_version(&version);
+            // The channel of the compiler itself wins over the one the
+            // toolchain is named after.
+            if let Some(channel) = directives::parse_channel(&version) {
+                project.channel = Some(channel.to_owned());
+            }
+        }
+
+        if project.timings.is_some() {
+            // The json format is unstable.
+            let json = match project.channel.as_ref().map(String::as_str) {
+                Some("nightly") | Some("dev") => cargo::supports_timings_json(&project),
+                _ => false,
+            };
+            if let Some(timings) = &mut project.timings {
+                timings.json = json;
+            }
+        }
+
+        for expanded in tests {
+            project
+                .bin_paths
+                .insert(expanded.name.0.clone(), expanded.test.path.clone());
+        }
 
-        let config = self.make_config();
+        let manifest = self.make_manifest(crate_name.clone(), &project, tests)?;
+        project.bindeps =
+            dependencies::get_manifest(&project.source_dir).has_artifact_dependencies();
+        let mut manifest_toml = toml::Value::try_from(&manifest)?;
+        for expanded in tests {
+            if let (Some(snippet), Value::Table(table)) = (&expanded.manifest, &mut manifest_toml) {
+                for (key, value) in snippet {
+                    if key != "bin" {
+                        merge_snippet(table, key, value.clone());
+                    }
+                }
+            }
+        }
+        let manifest_toml = toml::to_string(&manifest_toml)?;
+
+        let mut aux_manifests = Vec::new();
+        for (name, path) in aux_crates(tests) {
+            let aux_manifest = LibManifest {
+                package: Package {
+                    name: name.clone(),
+                    version: "0.0.0".to_owned(),
+                    edition: manifest.package.edition,
+                    publish: false,
+                    rust_version: None,
+                    build: None,
+                },
+                lib: Lib {
+                    path: project.source_dir.join(path),
+                    proc_macro: false,
+                },
+                // The crate under test, as the test cases see it.
+                dependencies: manifest
+                    .dependencies
+                    .get(&crate_name)
+                    .map(|dependency| (crate_name.clone(), dependency.clone()))
+                    .into_iter()
+                    .collect(),
+                lints: None,
+            };
+            aux_manifests.push((name, toml::to_string(&aux_manifest)?));
+        }
+        let mut proc_macro_manifests = Vec::new();
+        for expanded in tests {
+            if expanded.error.is_some() || !expanded.test.is_proc_macro(&project) {
+                continue;
+            }
+            let proc_macro_manifest = LibManifest {
+                package: Package {
+                    name: expanded.name.0.clone(),
+                    version: "0.0.0".to_owned(),
+                    edition: manifest.package.edition,
+                    publish: false,
+                    rust_version: None,
+                    build: None,
+                },
+                lib: Lib {
+                    path: project.source_dir.join(&expanded.test.path),
+                    proc_macro: true,
+                },
+                // Whatever the test cases have access to, such as syn and
+                // quote from the dev-dependencies.
+                dependencies: manifest.dependencies.clone(),
+                lints: manifest.lints.clone(),
+            };
+            let proc_macro_toml = toml::to_string(&proc_macro_manifest)?;
+            proc_macro_manifests.push((expanded.name.0.clone(), proc_macro_toml));
+        }
+
+        let config = self.make_config(&cargo_config, &project)?;
         let config_toml = toml::to_string(&config)?;
 
+        // Explicitly selected features that do not exist are left for Cargo to
+        // report.
         if let Some(enabled_features) = &mut project.features {
-            enabled_features.retain(|feature| manifest.features.contains_key(feature));
+            let selected = selected.as_ref();
+            enabled_features.retain(|feature| {
+                manifest.features.contains_key(feature)
+                    || selected.map_or(false, |selected| selected.contains(feature))
+            });
+        }
+
+        let build_script = match &self.build_script {
+            Some(BuildScript::Source(source)) => Some(source.as_str()),
+            Some(BuildScript::Path(_)) | None => None,
+        };
+        let mut all_manifests = manifest_toml.clone();
+        for (_name, lib_toml) in aux_manifests.iter().chain(&proc_macro_manifests) {
+            all_manifests += lib_toml;
         }
+        let sources = dependency_sources(&manifest, &project, tests);
+        let fingerprint = fingerprint(
+            &project,
+            &all_manifests,
+            &config_toml,
+            build_script,
+            &sources,
+        );
 
         fs::create_dir_all(path!(project.dir / ".cargo"))?;
-        fs::write(path!(project.dir / ".cargo" / "config"), config_toml)?;
-        fs::write(path!(project.dir / "Cargo.toml"), manifest_toml)?;
-        fs::write(path!(project.dir / "main.rs"), b"fn main() {}\n")?;
+        // Cargo decides what to rebuild by mtime, so files whose contents are
+        // unchanged are left untouched to keep the previous build fresh.
+        write_if_changed(path!(project.dir / ".cargo" / "config.toml"), config_toml)?;
+        link_legacy_config(&path!(project.dir / ".cargo"))?;
+        write_if_changed(path!(project.dir / "Cargo.toml"), manifest_toml)?;
+        write_if_changed(path!(project.dir / "main.rs"), "fn main() {}\n")?;
+        for (name, aux_toml) in aux_manifests {
+            let aux_dir = path!(project.dir / "aux-build" / name);
+            fs::create_dir_all(&aux_dir)?;
+            write_if_changed(aux_dir.join("Cargo.toml"), aux_toml)?;
+        }
+        for (name, proc_macro_toml) in proc_macro_manifests {
+            let proc_macro_dir = path!(project.dir / "proc-macro" / name);
+            fs::create_dir_all(&proc_macro_dir)?;
+            write_if_changed(proc_macro_dir.join("Cargo.toml"), proc_macro_toml)?;
+        }
+        match &self.build_script {
+            Some(BuildScript::Source(source)) => {
+                write_if_changed(path!(project.dir / "build.rs"), source)?;
+            }
+            // Otherwise Cargo would pick up one written by an earlier run.
+            Some(BuildScript::Path(_)) | None => {
+                let _ = fs::remove_file(path!(project.dir / "build.rs"));
+            }
+        }
+
+        for expanded in tests {
+            if let Some(aux_dir) = expanded.aux_dir(&project) {
+                let source = fs::read(project.source_dir.join(&expanded.test.path))?;
+                let copied_path = expanded.copied_path(&project);
+                copy_dir(&aux_dir, copied_path.parent().unwrap())?;
+                write_if_changed(copied_path, source)?;
+            }
+        }
+
+        if self.share_lockfile {
+            let workspace_lockfile = path!(project.workspace / "Cargo.lock");
+            if workspace_lockfile.exists() {
+                let lockfile = fs::read(workspace_lockfile)?;
+                write_if_changed(path!(project.dir / "Cargo.lock"), lockfile)?;
+                project.shared_lockfile = true;
+            }
+        }
+
+        // Nothing gets built.
+        if project.archive == Some(archive::Mode::Replay) {
+            return Ok(project);
+        }
+
+        // If nothing that goes into Cargo.lock or into building the
+        // dependencies, including the crate under test, changed since the
+        // last run, both are still up to date.
+        let fingerprint_path = path!(project.dir / ".trybuild-fingerprint");
+        if project.timings.is_none()
+            && path!(project.dir / "Cargo.lock").exists()
+            && fs::read_to_string(&fingerprint_path).ok().as_ref() == Some(&fingerprint)
+        {
+            return Ok(project);
+        }
+        let _ = fs::remove_file(&fingerprint_path);
 
         cargo::build_dependencies(&project)?;
 
+        // Failing to record the fingerprint only costs time on the next run.
+        let _ = fs::write(fingerprint_path, fingerprint);
+
         Ok(project)
     }
 
@@ -121,17 +720,38 @@ impl Runner {
         project: &Project,
         tests: &[ExpandedTest],
     ) -> Result<Manifest> {
-        let source_manifest = dependencies::get_manifest(&project.source_dir);
-        let workspace_manifest = dependencies::get_workspace_manifest(&project.workspace);
-
-        let features = source_manifest
-            .features
-            .keys()
-            .map(|feature| {
-                let enable = format!("{}/{}", crate_name, feature);
-                (feature.clone(), vec![enable])
-            })
-            .collect();
+        let mut source_manifest = dependencies::get_manifest(&project.source_dir);
+        if let Some(crate_type) = source_manifest.unusable_crate_type() {
+            return Err(Error::UnusableCrateType(crate_type.to_owned()));
+        }
+        let mut workspace_manifest = dependencies::get_workspace_manifest(&project.workspace);
+        if project.source_dir != project.workspace {
+            let package_manifest = dependencies::get_workspace_manifest(&project.source_dir);
+            dependencies::merge_patches(&mut workspace_manifest, package_manifest);
+        }
+        let workspace_dependencies = &workspace_manifest.workspace.dependencies;
+        dependencies::inherit(&mut source_manifest.dependencies, workspace_dependencies);
+        dependencies::inherit(
+            &mut source_manifest.dev_dependencies,
+            workspace_dependencies,
+        );
+        for target in source_manifest.target.values_mut() {
+            dependencies::inherit(&mut target.dependencies, workspace_dependencies);
+            dependencies::inherit(&mut target.dev_dependencies, workspace_dependencies);
+        }
+        if let Some(allowed) = &self.dev_dependencies {
+            let keep = |name: &String| allowed.contains(name);
+            retain_keys(&mut source_manifest.dev_dependencies, keep);
+            for target in source_manifest.target.values_mut() {
+                retain_keys(&mut target.dev_dependencies, keep);
+            }
+        }
+
+        let features = dependencies::forward_features(&crate_name, &source_manifest);
+
+        let lints = dependencies::lints(&source_manifest, &workspace_manifest);
+        let resolver = Some(dependencies::resolver(&workspace_manifest));
+        let rust_version = dependencies::rust_version(&source_manifest, &workspace_manifest);
 
         let mut manifest = Manifest {
             package: Package {
@@ -139,82 +759,237 @@ impl Runner {
                 version: "0.0.0".to_owned(),
                 edition: source_manifest.package.edition,
                 publish: false,
+                rust_version,
+                build: match &self.build_script {
+                    Some(BuildScript::Path(path)) => Some(project.source_dir.join(path)),
+                    Some(BuildScript::Source(_)) => Some(PathBuf::from("build.rs")),
+                    None => None,
+                },
             },
             features,
             dependencies: Map::new(),
+            build_dependencies: Map::new(),
+            target: Map::new(),
             bins: Vec::new(),
-            workspace: Some(Workspace {}),
-            // Within a workspace, only the [patch] and [replace] sections in
-            // the workspace root's Cargo.toml are applied by Cargo.
+            workspace: Some(Workspace {
+                members: Vec::new(),
+                resolver,
+            }),
+            // Within a workspace, only the [patch], [replace] and [profile]
+            // sections in the workspace root's Cargo.toml are applied by
+            // Cargo. Patches from the package were merged in above.
             patch: workspace_manifest.patch,
             replace: workspace_manifest.replace,
+            profile: workspace_manifest.profile,
+            lints,
         };
 
+        for (profile, settings) in &self.profiles {
+            let settings: config::Table = toml::from_str(settings)?;
+            manifest
+                .profile
+                .entry(profile.clone())
+                .or_default()
+                .extend(settings);
+        }
+
+        for (lint, level) in &self.lints {
+            let (tool, name) = match lint.find("::") {
+                Some(i) => (&lint[..i], &lint[i + 2..]),
+                None => ("rust", lint.as_str()),
+            };
+            let tools = manifest.lints.get_or_insert_with(Map::new);
+            let table = tools
+                .entry(tool.to_owned())
+                .or_insert_with(|| Value::Table(config::Table::new()));
+            if let Value::Table(table) = table {
+                table.insert(name.to_owned(), Value::String(level.clone()));
+            }
+        }
+
         manifest.dependencies.extend(source_manifest.dependencies);
         manifest
             .dependencies
             .extend(source_manifest.dev_dependencies);
+
+        for dependencies in &self.build_dependencies {
+            let mut dependencies = toml::from_str(dependencies)?;
+            dependencies::fix_dependencies(&mut dependencies, &project.source_dir);
+            manifest.build_dependencies.extend(dependencies);
+        }
+
+        for (cfg, target) in source_manifest.target {
+            let mut dependencies = target.dependencies;
+            dependencies.extend(target.dev_dependencies);
+            manifest.target.insert(cfg, Target { dependencies });
+        }
+
+        for (name, features) in &self.dependency_features {
+            let dependency = dependencies::find_mut(&mut manifest.dependencies, name)
+                .ok_or_else(|| Error::UnknownDependency(name.clone()))?;
+            dependency.features.extend(features.iter().cloned());
+        }
+        for (name, enabled) in &self.dependency_default_features {
+            let dependency = dependencies::find_mut(&mut manifest.dependencies, name)
+                .ok_or_else(|| Error::UnknownDependency(name.clone()))?;
+            dependency.default_features = *enabled;
+        }
+
         manifest.dependencies.insert(
             crate_name,
             Dependency {
                 version: None,
                 path: Some(project.source_dir.clone()),
-                default_features: false,
-                features: Vec::new(),
+                default_features: self.crate_default_features && self.default_features,
+                features: self.crate_features.clone(),
                 rest: Map::new(),
             },
         );
 
+        for name in aux_crates(tests).keys() {
+            manifest.dependencies.insert(
+                name.clone(),
+                Dependency {
+                    version: None,
+                    path: Some(path!(project.dir / "aux-build" / name)),
+                    default_features: true,
+                    features: Vec::new(),
+                    rest: Map::new(),
+                },
+            );
+        }
+
         manifest.bins.push(Bin {
             name: Name(project.name.to_owned()),
             path: Path::new("main.rs").to_owned(),
+            rest: config::Table::new(),
         });
 
         for expanded in tests {
-            if expanded.error.is_none() {
-                manifest.bins.push(Bin {
+            if expanded.error.is_none() && expanded.test.is_proc_macro(project) {
+                let workspace = manifest.workspace.as_mut().unwrap();
+                workspace
+                    .members
+                    .push(path!("proc-macro" / expanded.name.0));
+            } else if expanded.error.is_none() && !expanded.test.is_crate() {
+                let path = match expanded.aux_dir(project) {
+                    Some(_) => expanded.copied_path(project),
+                    None => expanded.test.source_path(project),
+                };
+                let mut bin = Bin {
                     name: expanded.name.clone(),
-                    path: project.source_dir.join(&expanded.test.path),
-                });
+                    path,
+                    rest: config::Table::new(),
+                };
+                if let Some(Value::Table(settings)) = expanded.manifest_snippet("bin") {
+                    bin.rest = settings.clone();
+                }
+                manifest.bins.push(bin);
             }
         }
 
         Ok(manifest)
     }
 
-    fn make_config(&self) -> Config {
-        Config {
-            build: Build {
-                rustflags: rustflags::make_vec(),
-            },
+    fn make_config(&self, cargo_config: &config::Table, project: &Project) -> Result<Config> {
+        let unseen = config::load_unseen(&project.source_dir, &project.dir);
+        let mut rustflags = config::build_rustflags(&unseen);
+        rustflags.extend(rustflags::make_vec().into_iter().map(str::to_owned));
+
+        // Settings that are needed to fetch and build the same dependencies
+        // as the crate under test.
+        let mut rest = config::passthrough(&unseen);
+        if let Some(wrapper) = &self.run_wrapper {
+            if let Some(source) = config::runner_source(cargo_config) {
+                return Err(Error::RunnerConflict(source));
+            }
+            config::set_runner(&mut rest, wrapper);
         }
+
+        Ok(Config {
+            build: Build { rustflags },
+            rest,
+        })
     }
 }
 
 impl Test {
     fn run(&self, project: &Project, name: &Name) -> Result<()> {
         let show_expected = project.has_pass && project.has_compile_fail;
-        message::begin_test(self, show_expected);
-        check_exists(&self.path)?;
-
-        let output = cargo::build_test(project, name)?;
-        let success = output.status.success();
-        let stdout = output.stdout;
-        let stderr = normalize::diagnostics(
-            output.stderr,
-            Context {
-                krate: &name.0,
-                source_dir: &project.source_dir,
-                workspace: &project.workspace,
-            },
-        );
+        message::begin_test(self, show_expected, project.display_label());
+        check_exists(&self.source_path(project))?;
+
+        let _span = span!("test", path = %self.path.display(), expected = ?self.expected);
+        let verbose = project.is_verbose(&self.path);
+        let mut status_lines = String::new();
+        let recording = if project.archive == Some(archive::Mode::Replay) {
+            archive::load(&project.archive_dir(), &project.source_dir, &self.path)?
+        } else {
+            let build = {
+                let _span = span!("build_test");
+                if self.is_crate() {
+                    cargo::build_crate(project, &project.source_dir.join(&self.path), !verbose)?
+                } else if self.is_proc_macro(project) {
+                    cargo::build_proc_macro(project, name, !verbose)?
+                } else {
+                    cargo::build_test(project, name, !verbose)?
+                }
+            };
+            status_lines = build.status_lines;
+            let recording = archive::Recording {
+                name: name.0.clone(),
+                success: build.output.status.success(),
+                stdout: String::from_utf8_lossy(&build.output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&build.output.stderr).into_owned(),
+                diagnostics: build.diagnostics,
+            };
+            if project.archive == Some(archive::Mode::Record) {
+                archive::save(
+                    &project.archive_dir(),
+                    &project.source_dir,
+                    &self.path,
+                    &recording,
+                )?;
+            }
+            recording
+        };
+        let success = recording.success;
+        let stdout = recording.stdout.into_bytes();
+        let copies = self.copies(project, &recording.name);
+        let context = Context {
+            // The name of the bin when the output was recorded, which is
+            // not necessarily the same when replaying only some test cases.
+            krate: &recording.name,
+            source_dir: &project.source_dir,
+            workspace: &project.workspace,
+            target_spec: project.target_spec.as_ref().map(PathBuf::as_path),
+            roots: &project.roots,
+            sysroot: project.sysroot.as_ref().map(PathBuf::as_path),
+            custom: &project.normalizations,
+            copies: &copies,
+            bin_paths: &project.bin_paths,
+        };
+        event!(success, "built test case");
+        let stderr = {
+            let _span = span!("normalize");
+            match self.expected {
+                Expected::CompileFail if project.json_snapshots && !success => {
+                    Variations::single(json::snapshot(&recording.diagnostics, context))
+                }
+                _ => normalize::diagnostics(recording.stderr.into_bytes(), context),
+            }
+        };
 
         let check = match self.expected {
             Expected::Pass => Test::check_pass,
             Expected::CompileFail => Test::check_compile_fail,
         };
 
-        check(self, project, name, success, stdout, stderr)
+        let result = check(self, project, name, success, stdout, stderr);
+        if verbose {
+            message::cargo_status(&status_lines);
+        }
+        result
     }
 
     fn check_pass(
@@ -231,14 +1006,64 @@ impl Test {
             return Err(Error::CargoFail);
         }
 
-        let mut output = cargo::run_test(project, name)?;
+        // Where the program runs: the generated project, or the test case's
+        // own directory if it is a crate.
+        let work_dir = if self.is_crate() {
+            project.source_dir.join(&self.path)
+        } else {
+            project.dir.clone()
+        };
+        let before = if project.deny_stray_files {
+            Some(list_files(&work_dir)?)
+        } else {
+            None
+        };
+
+        let mut output = {
+            let _span = span!("run_test");
+            if self.is_proc_macro(project) {
+                // Nothing to run but the compiler.
+                message::ok();
+                message::warnings(preferred);
+                return Ok(());
+            } else if !self.is_crate() {
+                cargo::run_test(project, name)?
+            } else {
+                let dir = project.source_dir.join(&self.path);
+                if !has_bin(&dir) {
+                    // A library passes by building successfully.
+                    message::ok();
+                    message::warnings(preferred);
+                    return Ok(());
+                }
+                cargo::run_crate(project, &dir)?
+            }
+        };
         output.stdout.splice(..0, build_stdout);
         message::output(preferred, &output);
-        if output.status.success() {
-            Ok(())
-        } else {
-            Err(Error::RunFailed)
+        if !output.status.success() {
+            return Err(Error::RunFailed);
         }
+
+        if let Some(before) = before {
+            let stray: Vec<PathBuf> = list_files(&work_dir)?
+                .into_iter()
+                .filter(|path| !before.contains(path))
+                .collect();
+            if !stray.is_empty() {
+                // The generated project belongs to trybuild, so files left
+                // there are removed to be caught again on the next run. A
+                // crate's directory is the user's, where nothing is deleted.
+                if !self.is_crate() {
+                    for path in &stray {
+                        let _ = fs::remove_file(path);
+                    }
+                }
+                return Err(Error::StrayFiles(stray));
+            }
+        }
+
+        Ok(())
     }
 
     fn check_compile_fail(
@@ -258,46 +1083,95 @@ impl Test {
             return Err(Error::ShouldNotHaveCompiled);
         }
 
-        let stderr_path = self.path.with_extension("stderr");
+        let stderr_path = self.stderr_path(project);
+        let update = self.update(project);
 
         if !stderr_path.exists() {
-            match project.update {
-                Update::Wip => {
-                    let wip_dir = Path::new("wip");
-                    fs::create_dir_all(wip_dir)?;
-                    let gitignore_path = wip_dir.join(".gitignore");
-                    fs::write(gitignore_path, "*\n")?;
+            match update {
+                Update::Wip | Update::__Nonexhaustive => {
+                    let wip_dir = &project.wip_dir;
+                    create_wip_dir(wip_dir)?;
                     let stderr_name = stderr_path
                         .file_name()
                         .unwrap_or_else(|| OsStr::new("test.stderr"));
                     let wip_path = wip_dir.join(stderr_name);
                     message::write_stderr_wip(&wip_path, &stderr_path, preferred);
                     fs::write(wip_path, preferred).map_err(Error::WriteStderr)?;
+                    let entry = Pending {
+                        snapshot: stderr_name.to_string_lossy().into_owned(),
+                        destination: stderr_path,
+                    };
+                    pending::record(wip_dir, entry)?;
                 }
                 Update::Overwrite => {
+                    let stderr_path = self.new_stderr_path(project, stderr_path);
                     message::overwrite_stderr(&stderr_path, preferred);
                     fs::write(stderr_path, preferred).map_err(Error::WriteStderr)?;
                 }
+                Update::Check => {
+                    message::missing_stderr(&stderr_path, preferred);
+                    message::fail_output(Fail, &build_stdout);
+                    return Err(Error::MissingStderr);
+                }
             }
             message::fail_output(Warn, &build_stdout);
             return Ok(());
         }
 
-        let expected = fs::read_to_string(&stderr_path)
-            .map_err(Error::ReadStderr)?
-            .replace("\r\n", "\n");
+        let elide = |output: &str| {
+            let mut output = output.to_owned();
+            if project.ignore_notes {
+                output = normalize::elide(&output, "note");
+            }
+            if project.ignore_help {
+                output = normalize::elide(&output, "help");
+            }
+            if project.ignore_feature_gates {
+                output = normalize::elide_feature_gates(&output);
+            }
+            output
+        };
+        let matches = |expected: &str| {
+            let elided = elide(expected);
+            variations.any(|stderr| {
+                if project.json_snapshots {
+                    json::matches(expected, stderr)
+                } else {
+                    volatile::matches(&elided, &elide(stderr))
+                }
+            })
+        };
 
-        if variations.any(|stderr| expected == stderr) {
-            message::ok();
-            return Ok(());
+        // The output may match foo.stderr or any of foo.alt1.stderr,
+        // foo.alt2.stderr, and so on. Mismatches are reported and overwritten
+        // against foo.stderr.
+        let mut expected = None;
+        for path in iter::once(stderr_path.clone()).chain(alternate_paths(&stderr_path)) {
+            let raw = fs::read_to_string(&path).map_err(Error::ReadStderr)?;
+            let (snapshot, problems) = normalize::snapshot(&raw);
+            if project.strict_snapshots && !problems.is_empty() {
+                message::malformed_stderr(&path, &problems);
+                return Err(Error::MalformedStderr);
+            }
+            if matches(&snapshot) {
+                message::ok();
+                return Ok(());
+            }
+            expected.get_or_insert(snapshot);
         }
+        let expected = expected.unwrap();
 
-        match project.update {
-            Update::Wip => {
+        match update {
+            Update::Wip | Update::Check | Update::__Nonexhaustive => {
                 message::mismatch(&expected, preferred);
-                Err(Error::Mismatch)
+                Err(Error::Mismatch(Mismatch {
+                    stderr_path,
+                    expected,
+                    actual: preferred.to_owned(),
+                }))
             }
             Update::Overwrite => {
+                let stderr_path = self.new_stderr_path(project, stderr_path);
                 message::overwrite_stderr(&stderr_path, preferred);
                 fs::write(stderr_path, preferred).map_err(Error::WriteStderr)?;
                 Ok(())
@@ -306,6 +1180,445 @@ impl Test {
     }
 }
 
+impl Test {
+    // A directory with a Cargo.toml of its own, for what a single file cannot
+    // express: a build script, a library, several crates in a workspace.
+    fn is_crate(&self) -> bool {
+        self.path.join("Cargo.toml").is_file()
+    }
+
+    // Modules of a test case tests/ui/foo.rs with a `// trybuild-modules`
+    // directive live in tests/ui/foo/. Rustc looks for the modules of a crate
+    // root next to the root itself, so the test case is built from a copy in a
+    // directory of its own next to copies of them.
+    fn aux_dir(&self, project: &Project) -> Option<PathBuf> {
+        if self.is_crate() {
+            return None;
+        }
+        let path = project.source_dir.join(&self.path);
+        let aux_dir = path.with_extension("");
+        if directives::parse(&path).modules
+            && aux_dir.is_dir()
+            && !aux_dir.join("Cargo.toml").exists()
+        {
+            Some(aux_dir)
+        } else {
+            None
+        }
+    }
+
+    fn copied_path(&self, project: &Project, name: &str) -> PathBuf {
+        let file_name = self.path.file_name().unwrap_or_default();
+        path!(project.dir / "aux" / name / file_name)
+    }
+
+    // The file that is built: the test case itself, or the one that an
+    // example from doc_examples was written to.
+    fn source_path(&self, project: &Project) -> PathBuf {
+        match &self.extracted {
+            Some(extracted) => extracted.clone(),
+            None => project.source_dir.join(&self.path),
+        }
+    }
+
+    // The copies that the test case was built from, each with its original, so
+    // that diagnostics point at the files in tests/ui/ rather than at a
+    // directory named after the bin. Cargo hands rustc paths relative to the
+    // generated project, so those are listed too. An extracted example counts
+    // as a copy of the file it would be next to its snapshot.
+    fn copies(&self, project: &Project, name: &str) -> Vec<(PathBuf, PathBuf)> {
+        if let Some(extracted) = &self.extracted {
+            return vec![(extracted.clone(), project.source_dir.join(&self.path))];
+        }
+        let aux_dir = match self.aux_dir(project) {
+            Some(aux_dir) => aux_dir,
+            None => return Vec::new(),
+        };
+        let original = project.source_dir.join(&self.path);
+        let copied_path = self.copied_path(project, name);
+        let relative = copied_path
+            .strip_prefix(&project.dir)
+            .unwrap_or(&copied_path)
+            .to_owned();
+        let mut copies = Vec::new();
+        for copied_path in vec![copied_path.clone(), relative] {
+            let copied_dir = copied_path.parent().unwrap().to_owned();
+            copies.push((copied_path, original.clone()));
+            copies.push((copied_dir, aux_dir.clone()));
+        }
+        copies
+    }
+
+    fn is_proc_macro(&self, project: &Project) -> bool {
+        !self.is_crate() && directives::parse(&project.source_dir.join(&self.path)).proc_macro
+    }
+
+    // A `// trybuild-update:` directive wins over TRYBUILD, whichever mode
+    // that is.
+    fn update(&self, project: &Project) -> Update {
+        let path = project.source_dir.join(&self.path);
+        directives::parse(&path).update.unwrap_or(project.update)
+    }
+
+    fn stderr_path(&self, project: &Project) -> PathBuf {
+        let extension = snapshot_extension(project);
+        let qualifiers = [
+            project.snapshot_suffix.as_ref().map(String::as_str),
+            project.channel.as_ref().map(String::as_str),
+            Some(target_os(project.target.as_ref().map(String::as_str))),
+        ];
+        for qualifier in qualifiers.iter().filter_map(|qualifier| *qualifier) {
+            let qualified_path = self
+                .path
+                .with_extension(format!("{}.{}", qualifier, extension));
+            if qualified_path.exists() {
+                return qualified_path;
+            }
+        }
+        self.path.with_extension(extension)
+    }
+
+    // Where to write the output of a run that does not match its snapshot.
+    // With a feature set or several toolchains, foo.stderr is left to the
+    // other runs and the output goes to foo.serde+std.stderr or
+    // foo.nightly.stderr, which is where stderr_path looks on the next run.
+    fn new_stderr_path(&self, project: &Project, stderr_path: PathBuf) -> PathBuf {
+        let extension = snapshot_extension(project);
+        if stderr_path != self.path.with_extension(extension) {
+            return stderr_path;
+        }
+        let qualifier = if project.snapshot_suffix.is_some() {
+            project.snapshot_suffix.as_ref()
+        } else if project.channel_snapshots {
+            project.channel.as_ref()
+        } else {
+            None
+        };
+        match qualifier {
+            Some(qualifier) => self
+                .path
+                .with_extension(format!("{}.{}", qualifier, extension)),
+            None => stderr_path,
+        }
+    }
+}
+
+fn snapshot_extension(project: &Project) -> &'static str {
+    if project.json_snapshots {
+        "json"
+    } else {
+        "stderr"
+    }
+}
+
+impl Project {
+    fn is_verbose(&self, path: &Path) -> bool {
+        self.verbose
+            .iter()
+            .any(|pattern| pattern.matches_path(path))
+    }
+
+    fn display_label(&self) -> Option<&str> {
+        self.label.as_ref().map(String::as_str)
+    }
+
+    // wip/archive, or a directory in there for each toolchain or feature set
+    // if there are several.
+    fn archive_dir(&self) -> PathBuf {
+        let mut dir = self.wip_dir.join("archive");
+        if let Some(label) = self.display_label() {
+            let label: String = label
+                .chars()
+                .map(|ch| if ch.is_alphanumeric() { ch } else { '-' })
+                .collect();
+            dir.push(label);
+        }
+        dir
+    }
+}
+
+// foo.stderr -> [foo.alt1.stderr, foo.alt2.stderr], as many as exist.
+fn alternate_paths(stderr_path: &Path) -> Vec<PathBuf> {
+    let extension = stderr_path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let mut paths = Vec::new();
+    for n in 1.. {
+        let path = stderr_path.with_extension(format!("alt{}.{}", n, extension));
+        if !path.exists() {
+            break;
+        }
+        paths.push(path);
+    }
+    paths
+}
+
+// "x86_64-pc-windows-msvc" -> "windows"
+// "aarch64-apple-darwin" -> "macos"
+// Target triple component -> name used in *.stderr file names.
+pub(crate) const TARGET_OS: &[(&str, &str)] = &[
+    ("windows", "windows"),
+    ("darwin", "macos"),
+    ("apple-ios", "ios"),
+    ("android", "android"),
+    ("linux", "linux"),
+    ("freebsd", "freebsd"),
+    ("netbsd", "netbsd"),
+    ("openbsd", "openbsd"),
+    ("wasi", "wasi"),
+];
+
+fn target_os(target: Option<&str>) -> &str {
+    let target = match target {
+        Some(target) => target,
+        None => return env::consts::OS,
+    };
+    let os = TARGET_OS
+        .iter()
+        .find(|(component, _os)| target.contains(component));
+    match os {
+        Some((_component, os)) => os,
+        None => "unknown",
+    }
+}
+
+// "nightly", "features: serde+derive", or "nightly, features: serde+derive"
+// depending on which of them vary between runs.
+fn display_label(toolchain: Option<&str>, feature_set: Option<&[String]>) -> Option<String> {
+    let features = feature_set.map(|set| {
+        if set.is_empty() {
+            "no features".to_owned()
+        } else {
+            format!("features: {}", set.join("+"))
+        }
+    });
+    match (toolchain, features) {
+        (Some(toolchain), Some(features)) => Some(format!("{}, {}", toolchain, features)),
+        (Some(toolchain), None) => Some(toolchain.to_owned()),
+        (None, features) => features,
+    }
+}
+
+fn retain_keys<V>(map: &mut Map<String, V>, keep: impl Fn(&String) -> bool) {
+    let remove: Vec<String> = map.keys().filter(|key| !keep(key)).cloned().collect();
+    for key in remove {
+        map.remove(&key);
+    }
+}
+
+fn outcome(test: &Test, label: &Option<String>, status: Status, duration: Duration) -> Outcome {
+    Outcome {
+        path: test.path.clone(),
+        label: label.clone(),
+        status,
+        duration,
+        reason: None,
+        error: None,
+    }
+}
+
+// Everything that goes into Cargo.lock or into building the dependencies,
+// hashed with a hash that stays the same from one Rust release to the next.
+fn fingerprint(
+    project: &Project,
+    manifest_toml: &str,
+    config_toml: &str,
+    build_script: Option<&str>,
+    sources: &[PathBuf],
+) -> String {
+    let mut hasher = hash::Fnv::default();
+    let mut write = |input: &dyn Debug| hasher.write(format!("{:?}\n", input).as_bytes());
+    write(&manifest_toml);
+    write(&config_toml);
+    write(&build_script);
+    write(&project.has_pass);
+    write(&project.features);
+    write(&project.all_features);
+    write(&project.target);
+    write(&project.toolchain);
+    write(&project.rustc_wrapper);
+    write(&project.rustc);
+    write(&project.offline);
+    write(&project.cargo_args);
+    write(&project.cargo_target_dir);
+    write(&project.release);
+    write(&env::var_os("RUSTFLAGS"));
+    // Git dependencies are pinned to the commit in the workspace's lockfile.
+    write(&fs::read(path!(project.workspace / "Cargo.lock")).ok());
+    for (path, modified) in modified_times(sources) {
+        write(&path);
+        write(&modified);
+    }
+    format!("{:016x}\n", hasher.finish())
+}
+
+// The sources of the crate under test and of its other path dependencies, and
+// of helper crates. Those are built along with the dependencies, and an error
+// in them should be reported there once rather than again for every test case.
+fn dependency_sources(
+    manifest: &Manifest,
+    project: &Project,
+    tests: &[ExpandedTest],
+) -> Vec<PathBuf> {
+    let targets = manifest.target.values().map(|target| &target.dependencies);
+    let mut sources: Vec<PathBuf> = iter::once(&manifest.dependencies)
+        .chain(iter::once(&manifest.build_dependencies))
+        .chain(targets)
+        .flat_map(Map::values)
+        .filter_map(|dep| dep.path.as_ref())
+        .flat_map(|dir| {
+            vec![
+                dir.join("Cargo.toml"),
+                dir.join("build.rs"),
+                dir.join("src"),
+            ]
+        })
+        .collect();
+    sources.extend(
+        aux_crates(tests)
+            .values()
+            .map(|path| project.source_dir.join(path)),
+    );
+    sources
+}
+
+// Every file among or inside of `paths`, with its modification time.
+fn modified_times(paths: &[PathBuf]) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut modified_times = Vec::new();
+    let mut paths = paths.to_vec();
+    while let Some(path) = paths.pop() {
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                paths.extend(entries.filter_map(|entry| Some(entry.ok()?.path())));
+            }
+        } else {
+            modified_times.push((path, metadata.modified().ok()));
+        }
+    }
+    modified_times.sort();
+    modified_times
+}
+
+fn has_bin(crate_dir: &Path) -> bool {
+    let declares_bin = fs::read_to_string(crate_dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|manifest| toml::from_str::<config::Table>(&manifest).ok())
+        .map_or(false, |manifest| manifest.contains_key("bin"));
+    declares_bin
+        || path!(crate_dir / "src" / "main.rs").exists()
+        || path!(crate_dir / "src" / "bin").is_dir()
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            write_if_changed(dest, fs::read(entry.path())?)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_if_changed(path: PathBuf, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let contents = contents.as_ref();
+    if fs::read(&path).ok().as_ref().map(|v| &v[..]) == Some(contents) {
+        return Ok(());
+    }
+    fs::write(path, contents)
+}
+
+// Cargo older than 1.39 only reads .cargo/config, which newer Cargo warns about
+// in every build unless it is a symlink to config.toml. Elsewhere than on Unix
+// only config.toml is written.
+fn link_legacy_config(cargo_dir: &Path) -> io::Result<()> {
+    let legacy = cargo_dir.join("config");
+    if fs::read_link(&legacy)
+        .ok()
+        .map_or(false, |target| target == Path::new("config.toml"))
+    {
+        return Ok(());
+    }
+    match fs::remove_file(&legacy) {
+        Ok(()) => {}
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("config.toml", legacy)?;
+    Ok(())
+}
+
+fn create_wip_dir(wip_dir: &Path) -> Result<()> {
+    fs::create_dir_all(wip_dir)?;
+    let gitignore_path = wip_dir.join(".gitignore");
+    fs::write(gitignore_path, "*\n")?;
+    Ok(())
+}
+
+// Collect the differences of every mismatched snapshot into one file so that
+// the overall effect of, say, a compiler upgrade can be reviewed at once.
+fn write_combined_diff(wip_dir: &Path, mismatches: &[Mismatch]) {
+    let mut combined = String::new();
+    for mismatch in mismatches {
+        let path = mismatch.stderr_path.to_string_lossy();
+        combined += &format!("--- {}\n+++ {} (actual)\n", path, path);
+        combined += &diff::lines(&mismatch.expected, &mismatch.actual);
+    }
+
+    let result = create_wip_dir(wip_dir).and_then(|()| {
+        let diff_path = wip_dir.join("mismatches.diff");
+        fs::write(&diff_path, combined)?;
+        Ok(diff_path)
+    });
+    match result {
+        Ok(diff_path) => message::wrote_combined_diff(&diff_path, mismatches.len()),
+        Err(err) => message::test_fail(&err),
+    }
+}
+
+// The actual output of mismatched test cases is kept next to the snapshots of
+// new test cases, for `cargo trybuild` to accept.
+fn write_pending_mismatches(wip_dir: &Path, mismatches: &[Mismatch]) -> Result<()> {
+    for mismatch in mismatches {
+        let stderr_name = match mismatch.stderr_path.file_name() {
+            Some(stderr_name) => stderr_name.to_string_lossy().into_owned(),
+            None => continue,
+        };
+        fs::write(wip_dir.join(&stderr_name), &mismatch.actual).map_err(Error::WriteStderr)?;
+        let entry = Pending {
+            snapshot: stderr_name,
+            destination: mismatch.stderr_path.clone(),
+        };
+        pending::record(wip_dir, entry)?;
+    }
+    Ok(())
+}
+
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
 fn check_exists(path: &Path) -> Result<()> {
     if path.exists() {
         return Ok(());
@@ -321,6 +1634,9 @@ struct ExpandedTest {
     name: Name,
     test: Test,
     error: Option<Error>,
+    manifest: Option<config::Table>,
+    // Helper crates from `// trybuild-aux-build:` directives.
+    aux_build: Vec<PathBuf>,
 }
 
 fn expand_globs(tests: &[Test]) -> Vec<ExpandedTest> {
@@ -343,6 +1659,8 @@ fn expand_globs(tests: &[Test]) -> Vec<ExpandedTest> {
             name: bin_name(vec.len()),
             test: test.clone(),
             error: None,
+            manifest: None,
+            aux_build: Vec::new(),
         };
         if let Some(utf8) = test.path.to_str() {
             if utf8.contains('*') {
@@ -354,8 +1672,11 @@ fn expand_globs(tests: &[Test]) -> Vec<ExpandedTest> {
                                 test: Test {
                                     path,
                                     expected: expanded.test.expected,
+                                    extracted: None,
                                 },
                                 error: None,
+                                manifest: None,
+                                aux_build: Vec::new(),
                             });
                         }
                         continue;
@@ -370,13 +1691,152 @@ fn expand_globs(tests: &[Test]) -> Vec<ExpandedTest> {
     vec
 }
 
+// Settings for the generated Cargo.toml that apply to a single test case,
+// taken from a file next to it with the same name and the extension .toml,
+// or from `// trybuild-manifest:` lines in the test case itself.
+//
+//     [dependencies]
+//     anyhow = "1.0"
+//
+//     [bin]
+//     required-features = ["std"]
+//
+// A [bin] table is merged into the test case's [[bin]] entry.
+fn load_manifest_snippets(tests: &mut [ExpandedTest]) {
+    for expanded in tests {
+        if expanded.error.is_some() || expanded.test.is_crate() {
+            continue;
+        }
+        let toml_path = expanded.test.path.with_extension("toml");
+        let mut snippet = fs::read_to_string(&toml_path).unwrap_or_default();
+        for line in directives::parse(&expanded.test.path).manifest {
+            snippet += &line;
+            snippet.push('\n');
+        }
+        if snippet.is_empty() {
+            continue;
+        }
+        match toml::from_str(&snippet) {
+            Ok(manifest) => expanded.manifest = Some(manifest),
+            Err(err) => expanded.error = Some(Error::TomlDe(err)),
+        }
+    }
+}
+
+// Helper crates for cross-crate tests, such as of the orphan rule, live in a
+// directory called aux next to the test cases that use them. A test case that
+// uses any is built in a project of its own, where they are its dependencies,
+// so that they are not in scope for the other test cases.
+//
+//     // trybuild-aux-build: foreign.rs
+//
+//     impl foreign::Trait for foreign::Type {}
+fn load_aux_build(tests: &mut [ExpandedTest]) {
+    for expanded in tests {
+        if expanded.error.is_some() || expanded.test.is_crate() {
+            continue;
+        }
+        let aux_dir = match expanded.test.path.parent() {
+            Some(parent) => parent.join("aux"),
+            None => PathBuf::from("aux"),
+        };
+        for file_name in directives::parse(&expanded.test.path).aux_build {
+            let path = aux_dir.join(file_name);
+            if let Err(err) = check_exists(&path) {
+                expanded.error = Some(err);
+                break;
+            }
+            expanded.aux_build.push(path);
+        }
+    }
+}
+
+// Package name and path of each helper crate needed by the test cases.
+fn aux_crates(tests: &[ExpandedTest]) -> Map<String, PathBuf> {
+    let mut aux_crates = Map::new();
+    for expanded in tests {
+        for path in &expanded.aux_build {
+            if let Some(stem) = path.file_stem() {
+                let name = stem.to_string_lossy().into_owned();
+                aux_crates.entry(name).or_insert_with(|| path.clone());
+            }
+        }
+    }
+    aux_crates
+}
+
+// Merge a setting from a test case's manifest snippet into the generated
+// Cargo.toml. Tables are merged key by key; anything else is replaced.
+fn merge_snippet(table: &mut config::Table, key: &str, value: Value) {
+    match (table.get_mut(key), value) {
+        (Some(Value::Table(existing)), Value::Table(snippet)) => {
+            for (key, value) in snippet {
+                merge_snippet(existing, &key, value);
+            }
+        }
+        (_, value) => {
+            table.insert(key.to_owned(), value);
+        }
+    }
+}
+
 impl ExpandedTest {
+    fn manifest_snippet(&self, key: &str) -> Option<&Value> {
+        self.manifest.as_ref()?.get(key)
+    }
+
+    // Whether the test case's manifest snippet has anything beyond settings
+    // for its own [[bin]] entry, or the test case needs helper crates.
+    fn is_isolated(&self) -> bool {
+        !self.aux_build.is_empty()
+            || self
+                .manifest
+                .as_ref()
+                .map_or(false, |manifest| manifest.keys().any(|key| key != "bin"))
+    }
+
+    fn aux_dir(&self, project: &Project) -> Option<PathBuf> {
+        if self.error.is_some() {
+            return None;
+        }
+        self.test.aux_dir(project)
+    }
+
+    fn copied_path(&self, project: &Project) -> PathBuf {
+        self.test.copied_path(project, &self.name.0)
+    }
+
+    // Whether the test case declares that it needs a newer compiler than the
+    // one building it.
+    fn is_too_new(&self, project: &Project) -> bool {
+        let rustc_version = match project.rustc_version {
+            Some(rustc_version) => rustc_version,
+            None => return false,
+        };
+        let path = project.source_dir.join(&self.test.path);
+        match directives::parse(&path).rust_version {
+            Some(required) => rustc_version < required,
+            None => false,
+        }
+    }
+
+    // Only the build of a test case is recorded, which is not enough to
+    // know whether one that should pass does.
+    fn is_unreplayable(&self, project: &Project) -> bool {
+        project.archive == Some(archive::Mode::Replay)
+            && self.error.is_none()
+            && match self.test.expected {
+                Expected::Pass => true,
+                Expected::CompileFail => false,
+            }
+    }
+
     fn run(self, project: &Project) -> Result<()> {
         match self.error {
             None => self.test.run(project, &self.name),
             Some(error) => {
                 let show_expected = false;
-                message::begin_test(&self.test, show_expected);
+                message::begin_test(&self.test, show_expected, project.display_label());
                 Err(error)
             }
         }
@@ -404,6 +1864,16 @@ fn filter(tests: &mut Vec<ExpandedTest>) {
             }
         })
         .collect::<Vec<String>>();
+    // Set by `cargo trybuild watch`, which has no way to name the #[test]
+    // function that would have to precede trybuild=... on the command line.
+    let filters = match env::var_os("TRYBUILD_FILTER") {
+        Some(var) => filters
+            .into_iter()
+            .chain(env::split_paths(&var).map(|f| f.to_string_lossy().into_owned()))
+            .filter(|f| !f.is_empty())
+            .collect(),
+        None => filters,
+    };
 
     if filters.is_empty() {
         return;
@@ -1,7 +1,12 @@
 use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
 use std::process::Command;
 
 const RUSTFLAGS: &str = "RUSTFLAGS";
+// Takes precedence over RUSTFLAGS in Cargo, and is what cargo-llvm-cov sets.
+// Flags are separated by 0x1f rather than by spaces.
+const ENCODED_RUSTFLAGS: &str = "CARGO_ENCODED_RUSTFLAGS";
 const IGNORED_LINTS: &[&str] = &["dead_code"];
 
 pub fn make_vec() -> Vec<&'static str> {
@@ -16,6 +21,17 @@ pub fn make_vec() -> Vec<&'static str> {
 }
 
 pub fn set_env(cmd: &mut Command) {
+    if let Some(mut rustflags) = env::var_os(ENCODED_RUSTFLAGS) {
+        for flag in make_vec() {
+            if !rustflags.is_empty() {
+                rustflags.push("\x1f");
+            }
+            rustflags.push(flag);
+        }
+        cmd.env(ENCODED_RUSTFLAGS, rustflags);
+        return;
+    }
+
     let mut rustflags = match env::var_os(RUSTFLAGS) {
         Some(rustflags) => rustflags,
         None => return,
@@ -28,3 +44,28 @@ pub fn set_env(cmd: &mut Command) {
 
     cmd.env(RUSTFLAGS, rustflags);
 }
+
+// When the tests themselves are being built with `-C instrument-coverage`, as
+// by `cargo llvm-cov`, the test cases are too because the flags above carry
+// over. Their profiles need to end up next to those of the tests in order to
+// be counted: a relative LLVM_PROFILE_FILE, or the default of
+// default_%m_%p.profraw, would otherwise be resolved against the directory of
+// the generated project, where every run of a test case writes its profile.
+pub fn set_profile_file(cmd: &mut Command) {
+    if !instrument_coverage() {
+        return;
+    }
+    let profile_file = env::var_os("LLVM_PROFILE_FILE")
+        .map_or_else(|| PathBuf::from("default_%m_%p.profraw"), PathBuf::from);
+    if profile_file.is_relative() {
+        if let Ok(cwd) = env::current_dir() {
+            cmd.env("LLVM_PROFILE_FILE", cwd.join(profile_file));
+        }
+    }
+}
+
+fn instrument_coverage() -> bool {
+    let contains = |flags: OsString| flags.to_string_lossy().contains("instrument-coverage");
+    env::var_os(ENCODED_RUSTFLAGS).map_or(false, contains)
+        || env::var_os(RUSTFLAGS).map_or(false, contains)
+}
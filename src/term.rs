@@ -1,5 +1,8 @@
 use lazy_static::lazy_static;
+use std::cell::Cell;
+use std::env;
 use std::io::{Result, Write};
+use std::path::Path;
 use std::sync::{Mutex, MutexGuard, PoisonError};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream as Stream, WriteColor};
 
@@ -7,8 +10,46 @@ lazy_static! {
     static ref TERM: Mutex<Term> = Mutex::new(Term::new());
 }
 
+thread_local! {
+    // Settings of the TestCases that is running on this thread, if any. They
+    // are not stored in TERM so that they do not carry over to other
+    // TestCases in the same test binary.
+    static SCOPE: Cell<Scope> = Cell::new(Scope::default());
+}
+
 pub fn lock() -> MutexGuard<'static, Term> {
-    TERM.lock().unwrap_or_else(PoisonError::into_inner)
+    let mut term = TERM.lock().unwrap_or_else(PoisonError::into_inner);
+    let choice = SCOPE.with(Cell::get).color.unwrap_or(term.default_choice);
+    if term.choice != choice {
+        term.stream = Stream::stderr(choice);
+        term.choice = choice;
+    }
+    term
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct Scope {
+    // Overrides the choice made from the environment.
+    pub color: Option<ColorChoice>,
+    // Caps the width of the terminal.
+    pub max_width: Option<usize>,
+}
+
+// Applies the settings of a run until the returned guard is dropped, after
+// which those of the enclosing run, if any, are back in effect.
+pub fn scope(scope: Scope) -> ScopeGuard {
+    let previous = SCOPE.with(|cell| cell.replace(scope));
+    ScopeGuard { previous }
+}
+
+pub struct ScopeGuard {
+    previous: Scope,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        SCOPE.with(|cell| cell.set(self.previous));
+    }
 }
 
 pub fn bold() {
@@ -27,6 +68,102 @@ pub fn reset() {
     lock().reset();
 }
 
+// Number of columns to fit the output in: the width of the terminal, or
+// $COLUMNS if set, capped by TestCases::max_width.
+pub fn width() -> Option<usize> {
+    let columns = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse::<usize>().ok())
+        .filter(|&columns| columns > 0)
+        .or_else(terminal_width);
+    match (columns, SCOPE.with(Cell::get).max_width) {
+        (Some(columns), Some(max_width)) => Some(columns.min(max_width)),
+        (columns, max_width) => columns.or(max_width),
+    }
+}
+
+// Trybuild's output goes to stderr, which is a terminal unless redirected.
+#[cfg(unix)]
+fn terminal_width() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret == 0 && size.ws_col > 0 {
+        Some(usize::from(size.ws_col))
+    } else {
+        None
+    }
+}
+
+#[cfg(windows)]
+fn terminal_width() -> Option<usize> {
+    use winapi_util::console;
+    use winapi_util::HandleRef;
+
+    let info = console::screen_buffer_info(HandleRef::stderr()).ok()?;
+    let window = info.window_rect();
+    let width = window.right - window.left + 1;
+    if width > 0 {
+        Some(width as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn terminal_width() -> Option<usize> {
+    None
+}
+
+// Makes `text` a link to the file at `path` in terminals that support OSC 8
+// hyperlinks: https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feda
+pub fn hyperlink(path: &Path, text: &str) -> String {
+    if !supports_hyperlinks() {
+        return text.to_owned();
+    }
+    let path = match env::current_dir() {
+        Ok(cwd) => cwd.join(path),
+        Err(_) => return text.to_owned(),
+    };
+    let url = path.to_string_lossy().replace(' ', "%20");
+    let url = if url.starts_with('/') {
+        format!("file://{}", url)
+    } else {
+        format!("file:///{}", url.replace('\\', "/"))
+    };
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+fn supports_hyperlinks() -> bool {
+    if let Some(force) = env::var_os("FORCE_HYPERLINK") {
+        return force != "0";
+    }
+    if !lock().stream.supports_color() {
+        return false;
+    }
+    let var = |name| env::var(name).unwrap_or_default();
+    let term_program = var("TERM_PROGRAM");
+    ["iTerm.app", "WezTerm", "vscode", "ghostty"].contains(&term_program.as_str())
+        || var("VTE_VERSION")
+            .parse::<u32>()
+            .ok()
+            .map_or(false, |version| version >= 5000)
+        || !var("WT_SESSION").is_empty()
+        || !var("KONSOLE_VERSION").is_empty()
+        || var("TERM") == "xterm-kitty"
+}
+
+// https://no-color.org and https://bixense.com/clicolors
+fn color_choice() -> ColorChoice {
+    let var = |name| env::var_os(name).filter(|value| !value.is_empty());
+    if var("CLICOLOR_FORCE").map_or(false, |value| value != "0") {
+        ColorChoice::Always
+    } else if var("NO_COLOR").is_some() || var("CLICOLOR").map_or(false, |value| value == "0") {
+        ColorChoice::Never
+    } else {
+        ColorChoice::Auto
+    }
+}
+
 #[deny(unused_macros)]
 macro_rules! print {
     ($($args:tt)*) => {{
@@ -46,14 +183,21 @@ macro_rules! println {
 pub struct Term {
     spec: ColorSpec,
     stream: Stream,
+    // The choice that `stream` was made with, and the one made from the
+    // environment.
+    choice: ColorChoice,
+    default_choice: ColorChoice,
     start_of_line: bool,
 }
 
 impl Term {
     fn new() -> Self {
+        let choice = color_choice();
         Term {
             spec: ColorSpec::new(),
-            stream: Stream::stderr(ColorChoice::Auto),
+            stream: Stream::stderr(choice),
+            choice,
+            default_choice: choice,
             start_of_line: true,
         }
     }
@@ -104,3 +248,22 @@ impl Write for Term {
         self.stream.flush()
     }
 }
+
+#[test]
+fn test_scope() {
+    let outer = scope(Scope {
+        color: Some(ColorChoice::Never),
+        max_width: None,
+    });
+    {
+        let _inner = scope(Scope {
+            color: Some(ColorChoice::Always),
+            max_width: None,
+        });
+        assert_eq!(lock().choice, ColorChoice::Always);
+    }
+    assert_eq!(lock().choice, ColorChoice::Never);
+    drop(outer);
+    let term = lock();
+    assert_eq!(term.choice, term.default_choice);
+}
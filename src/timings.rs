@@ -0,0 +1,263 @@
+// With TestCases::timings, every build of the generated project reports the
+// time Cargo spent on each compilation unit. Where Cargo supports it, that is
+// `cargo --timings=json`, which prints one JSON message per unit to stdout
+// alongside the others:
+//
+//     {
+//       "reason": "timing-info",
+//       "package_id": "path+file:///path/to/target/tests/trybuild/x#trybuild-tests@0.0.0",
+//       "target": {"kind": ["bin"], "name": "trybuild003", ...},
+//       "mode": "build",
+//       "duration": 0.07,
+//       ...
+//     }
+//
+// The json format is unstable and needs a nightly Cargo, and newer Cargo has
+// dropped it again. Everywhere else the build runs with plain `--timings` and
+// the same data is read back from the HTML report that Cargo writes to
+// $CARGO_TARGET_DIR/cargo-timings, which embeds it as a JSON array:
+//
+//     const UNIT_DATA = [
+//       {
+//         "i": 0,
+//         "name": "trybuild-tests",
+//         "version": "0.0.0",
+//         "target": " trybuild003 \"bin\"",
+//         "duration": 0.07,
+//         ...
+//       }
+//     ];
+//
+// The layout of the report is not a stable interface, so this is only a
+// fallback: if it changes, the units are missing from the summary rather than
+// the tests failing. A report older than the build is left over from an
+// earlier one, such as when Cargo found nothing to build or failed before
+// writing a report.
+
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fs;
+use std::time::SystemTime;
+
+use crate::run::Project;
+
+#[derive(Debug)]
+pub struct Timings {
+    // Whether Cargo accepts `--timings=json`.
+    pub json: bool,
+    units: RefCell<Vec<Unit>>,
+}
+
+#[derive(Deserialize)]
+struct TimingInfo {
+    reason: String,
+    package_id: String,
+    target: Target,
+    mode: String,
+    duration: f64,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct UnitData {
+    name: String,
+    target: String,
+    duration: f64,
+}
+
+#[derive(Debug)]
+pub struct Unit {
+    pub name: String,
+    pub test_case: bool,
+    pub duration: f64,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Timings {
+            json: false,
+            units: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn args(&self) -> &'static [&'static str] {
+        if self.json {
+            &["-Zunstable-options", "--timings=json"]
+        } else {
+            &["--timings"]
+        }
+    }
+}
+
+// Records the units of one build, given what Cargo printed to stdout.
+pub fn collect(project: &Project, stdout: &[u8], build_start: SystemTime) {
+    let timings = match &project.timings {
+        Some(timings) => timings,
+        None => return,
+    };
+
+    let mut units = timings.units.borrow_mut();
+    if timings.json {
+        for line in stdout.split(|&b| b == b'\n') {
+            let info: TimingInfo = match serde_json::from_slice(line) {
+                Ok(info) => info,
+                Err(_) => continue,
+            };
+            if info.reason != "timing-info" {
+                continue;
+            }
+            let target = target_description(&info.target, &info.mode);
+            let name = package_name(&info.package_id);
+            units.push(unit(project, name, &target, info.duration));
+        }
+    } else {
+        for data in read_report(project, build_start) {
+            units.push(unit(project, &data.name, data.target.trim(), data.duration));
+        }
+    }
+}
+
+fn read_report(project: &Project, build_start: SystemTime) -> Vec<UnitData> {
+    let path = path!(project.cargo_target_dir / "cargo-timings" / "cargo-timing.html");
+    let modified = fs::metadata(&path).and_then(|metadata| metadata.modified());
+    if modified
+        .ok()
+        .map_or(true, |modified| modified < build_start)
+    {
+        return Vec::new();
+    }
+    let html = match fs::read_to_string(path) {
+        Ok(html) => html,
+        Err(_) => return Vec::new(),
+    };
+
+    const START: &str = "const UNIT_DATA = ";
+    let json = match html.find(START) {
+        Some(start) => &html[start + START.len()..],
+        None => return Vec::new(),
+    };
+    let json = match json.find("];") {
+        Some(end) => &json[..end + 1],
+        None => return Vec::new(),
+    };
+    serde_json::from_str(json).unwrap_or_default()
+}
+
+fn unit(project: &Project, package: &str, target: &str, duration: f64) -> Unit {
+    let bin = target.split(' ').next().unwrap_or(target);
+    let test_case = package == project.name && project.bin_paths.contains_key(bin);
+    let name = if test_case {
+        project.bin_paths[bin].display().to_string()
+    } else {
+        format!("{} {}", package, target).trim_end().to_owned()
+    };
+    Unit {
+        name,
+        test_case,
+        duration,
+    }
+}
+
+// Described like the target in Cargo's HTML report, so that units are named
+// the same either way: nothing for a library, otherwise `trybuild003 "bin"`,
+// and `(run)` after a build script being run.
+fn target_description(target: &Target, mode: &str) -> String {
+    let kind = target.kind.first().map_or("", String::as_str);
+    let mut description = match kind {
+        "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro" => String::new(),
+        _ => format!("{} \"{}\"", target.name, kind),
+    };
+    if mode == "run-custom-build" {
+        description += " (run)";
+    }
+    description
+}
+
+// "path+file:///path/to/serde#1.0.104"
+// "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.104"
+// "serde 1.0.104 (registry+https://github.com/rust-lang/crates.io-index)"
+fn package_name(package_id: &str) -> &str {
+    match package_id.rfind('#') {
+        Some(hash) => {
+            let fragment = &package_id[hash + 1..];
+            match fragment.find('@') {
+                Some(at) => &fragment[..at],
+                // The name is left out when it is the last segment of the URL.
+                None => {
+                    let url = &package_id[..hash];
+                    let url = url.split('?').next().unwrap_or(url);
+                    url.rsplit('/').next().unwrap_or(url)
+                }
+            }
+        }
+        None => package_id.split(' ').next().unwrap_or(package_id),
+    }
+}
+
+// Total compile time per unit, longest first.
+pub fn summarize(project: &Project) -> Vec<Unit> {
+    let mut summary: Vec<Unit> = Vec::new();
+    let timings = match &project.timings {
+        Some(timings) => timings.units.borrow(),
+        None => return summary,
+    };
+    for unit in timings.iter() {
+        match summary.iter_mut().find(|sum| sum.name == unit.name) {
+            Some(sum) => sum.duration += unit.duration,
+            None => summary.push(Unit {
+                name: unit.name.clone(),
+                test_case: unit.test_case,
+                duration: unit.duration,
+            }),
+        }
+    }
+    summary.sort_by(|a, b| {
+        b.duration
+            .partial_cmp(&a.duration)
+            .unwrap_or(Ordering::Equal)
+    });
+    summary
+}
+
+#[test]
+fn test_package_name() {
+    assert_eq!(package_name("path+file:///path/to/serde#1.0.104"), "serde");
+    assert_eq!(
+        package_name("registry+https://github.com/rust-lang/crates.io-index#serde@1.0.104"),
+        "serde",
+    );
+    assert_eq!(
+        package_name("git+https://github.com/serde-rs/serde?branch=master#serde_derive@1.0.104"),
+        "serde_derive",
+    );
+    assert_eq!(
+        package_name("serde 1.0.104 (registry+https://github.com/rust-lang/crates.io-index)"),
+        "serde",
+    );
+}
+
+#[test]
+fn test_target_description() {
+    let target = |name: &str, kind: &str| Target {
+        name: name.to_owned(),
+        kind: vec![kind.to_owned()],
+    };
+    assert_eq!(target_description(&target("serde", "lib"), "build"), "");
+    assert_eq!(
+        target_description(&target("trybuild003", "bin"), "check"),
+        "trybuild003 \"bin\"",
+    );
+    assert_eq!(
+        target_description(
+            &target("build-script-build", "custom-build"),
+            "run-custom-build"
+        ),
+        "build-script-build \"custom-build\" (run)",
+    );
+}
@@ -0,0 +1,36 @@
+// With the "tracing" feature, trybuild reports what it is doing as spans of
+// the `tracing` crate:
+//
+//     let _span = span!("build_test", test = %path.display());
+//
+// Without the feature the macro expands to nothing of consequence, and its
+// arguments are not evaluated.
+
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($args:tt)*) => {
+        tracing::info_span!($($args)*).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($args:tt)*) => {
+        crate::trace::NoSpan
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($($args:tt)*) => {
+        tracing::debug!($($args)*)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($($args:tt)*) => {};
+}
+
+#[cfg(not(feature = "tracing"))]
+pub struct NoSpan;
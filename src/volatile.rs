@@ -0,0 +1,78 @@
+// Sections of a saved stderr file may be marked volatile, for example where
+// the compiler quotes source code of a dependency whose line numbers shift
+// every time the dependency is bumped:
+//
+//     error[E0277]: the trait bound `Thing: Trait` is not satisfied
+//     // trybuild-volatile-begin
+//       ::: $CARGO/dep-1.0.3/src/lib.rs:45:24
+//        |
+//     45 | pub fn require<T: Trait>(_: T) {}
+//        |                   ----- required by this bound in `require`
+//     // trybuild-volatile-end
+//
+// The lines between the markers match any number of lines of actual compiler
+// output, including none.
+
+const BEGIN: &str = "// trybuild-volatile-begin";
+const END: &str = "// trybuild-volatile-end";
+
+enum Segment<'a> {
+    Line(&'a str),
+    Volatile,
+}
+
+pub fn matches(expected: &str, actual: &str) -> bool {
+    if !expected.contains(BEGIN) {
+        return expected == actual;
+    }
+
+    let mut segments = Vec::new();
+    let mut in_volatile = false;
+    for line in expected.lines() {
+        if line == BEGIN {
+            if !in_volatile {
+                segments.push(Segment::Volatile);
+            }
+            in_volatile = true;
+        } else if line == END {
+            in_volatile = false;
+        } else if !in_volatile {
+            segments.push(Segment::Line(line));
+        }
+    }
+
+    let actual: Vec<&str> = actual.lines().collect();
+    matches_lines(&segments, &actual)
+}
+
+fn matches_lines(segments: &[Segment], actual: &[&str]) -> bool {
+    match segments.split_first() {
+        None => actual.is_empty(),
+        Some((Segment::Line(line), rest)) => match actual.split_first() {
+            Some((first, actual_rest)) => first == line && matches_lines(rest, actual_rest),
+            None => false,
+        },
+        Some((Segment::Volatile, rest)) => {
+            (0..=actual.len()).any(|skip| matches_lines(rest, &actual[skip..]))
+        }
+    }
+}
+
+#[test]
+fn test_volatile_matches() {
+    let expected = "\
+error: oops
+// trybuild-volatile-begin
+  ::: $CARGO/dep-1.0.3/src/lib.rs:45:24
+// trybuild-volatile-end
+  |
+";
+
+    assert!(matches(expected, "error: oops\n  |\n"));
+    assert!(matches(
+        expected,
+        "error: oops\n  ::: $CARGO/dep-1.0.4/src/lib.rs:47:24\n   |\n  |\n",
+    ));
+    assert!(!matches(expected, "error: other\n  |\n"));
+    assert!(matches("error: oops\n", "error: oops\n"));
+}
@@ -0,0 +1,133 @@
+// With TRYBUILD_WARM=1, the output of `cargo metadata` is saved across runs of
+// the test harness so that repeatedly running a single ui test while iterating
+// on it does not pay for Cargo resolving the whole workspace every time. The
+// dependency build and the generated project's Cargo.lock stay warm between
+// runs either way, for as long as nothing that goes into them changes.
+//
+// The saved state lives next to the test binary in the target directory:
+//
+//     target/debug/trybuild-warm/0123456789abcdef.json
+//
+// and is discarded as soon as anything that Cargo's answer depends on changes:
+// the crate's or the workspace's Cargo.toml, the workspace's Cargo.lock, any
+// Cargo config file, the toolchain, or the environment variables that point
+// Cargo elsewhere.
+
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cargo::{self, Metadata};
+use crate::config;
+use crate::error::Result;
+use crate::hash;
+
+#[derive(Serialize, Deserialize)]
+struct State {
+    fingerprint: String,
+    metadata: Metadata,
+}
+
+pub fn enabled() -> Result<bool> {
+    crate::env::flag("TRYBUILD_WARM", false)
+}
+
+pub fn metadata() -> Result<Metadata> {
+    if !enabled()? {
+        return cargo::metadata();
+    }
+
+    let manifest_dir = match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(manifest_dir) => PathBuf::from(manifest_dir),
+        None => return cargo::metadata(),
+    };
+    let state_path = match state_path(&manifest_dir) {
+        Some(state_path) => state_path,
+        None => return cargo::metadata(),
+    };
+
+    if let Some(state) = load(&state_path) {
+        let fingerprint = fingerprint(&manifest_dir, &state.metadata.workspace_root);
+        if state.fingerprint == fingerprint {
+            return Ok(state.metadata);
+        }
+    }
+
+    let metadata = cargo::metadata()?;
+    let state = State {
+        fingerprint: fingerprint(&manifest_dir, &metadata.workspace_root),
+        metadata,
+    };
+    // Failing to save the state only costs time on the next run.
+    if let Ok(json) = serde_json::to_vec(&state) {
+        if let Some(parent) = state_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(state_path, json);
+    }
+    Ok(state.metadata)
+}
+
+// The test binary is target/debug/deps/ui-0123456789abcdef, or the same under
+// target/<triple>. Outside of a target directory nothing is saved.
+fn state_path(manifest_dir: &Path) -> Option<PathBuf> {
+    let test_binary = env::current_exe().ok()?;
+    let deps_dir = test_binary.parent()?;
+    if deps_dir.file_name()? != "deps" {
+        return None;
+    }
+    let file_name = format!(
+        "{:016x}.json",
+        hash::hash(manifest_dir.to_string_lossy().as_bytes()),
+    );
+    Some(path!(deps_dir.parent()? / "trybuild-warm" / file_name))
+}
+
+fn load(state_path: &Path) -> Option<State> {
+    let json = fs::read(state_path).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+fn fingerprint(manifest_dir: &Path, workspace_root: &Path) -> String {
+    let mut hasher = hash::Fnv::default();
+    let mut write = |input: &dyn Debug| hasher.write(format!("{:?}\n", input).as_bytes());
+
+    write(&manifest_dir);
+    for var in &[
+        "CARGO",
+        "CARGO_HOME",
+        "CARGO_TARGET_DIR",
+        "CARGO_BUILD_TARGET_DIR",
+        "RUSTC",
+        "RUSTUP_TOOLCHAIN",
+    ] {
+        write(&env::var_os(var));
+    }
+
+    let mut files = vec![
+        manifest_dir.join("Cargo.toml"),
+        workspace_root.join("Cargo.toml"),
+        workspace_root.join("Cargo.lock"),
+    ];
+    // Updating a toolchain in place replaces its cargo binary.
+    files.extend(env::var_os("CARGO").map(PathBuf::from));
+    files.extend(option_env!("CARGO").map(PathBuf::from));
+    let config_dirs = manifest_dir
+        .ancestors()
+        .map(|dir| dir.join(".cargo"))
+        .chain(config::cargo_home());
+    for dir in config_dirs {
+        files.push(dir.join("config"));
+        files.push(dir.join("config.toml"));
+    }
+
+    for file in &files {
+        let modified = fs::metadata(file).and_then(|metadata| metadata.modified());
+        write(&file);
+        write(&modified.ok());
+    }
+
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,231 @@
+// `cargo trybuild watch` reruns the crate's tests whenever a file in src/ or
+// tests/, Cargo.toml or build.rs changes. Files are polled rather than watched
+// through the operating system, which is plenty for a crate's worth of sources.
+//
+// A change to a test case, to its modules, or to a snapshot or manifest snippet
+// next to it only reruns that test case, by way of TRYBUILD_FILTER. Files
+// directly in tests/ are test harnesses rather than test cases, and a change to
+// any of them or to the crate itself reruns everything.
+
+use std::collections::BTreeMap as Map;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use termcolor::Color::{Green, Red, Yellow};
+
+use crate::directives;
+use crate::term;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+type Snapshot = Map<PathBuf, Option<SystemTime>>;
+
+pub fn watch(crate_dir: &Path, filters: &[String], cargo_args: &[String]) -> io::Result<()> {
+    run(crate_dir, filters, cargo_args)?;
+    // Snapshots written by a run must not trigger another run.
+    let mut before = scan(crate_dir)?;
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let mut after = scan(crate_dir)?;
+        if after == before {
+            continue;
+        }
+        // Editors tend to write several files in quick succession.
+        thread::sleep(POLL_INTERVAL);
+        after = scan(crate_dir)?;
+
+        let changed = changed_paths(&before, &after);
+        match affected(crate_dir, &changed) {
+            Affected::All => run(crate_dir, filters, cargo_args)?,
+            Affected::Tests(tests) => {
+                let tests: Vec<String> = tests
+                    .into_iter()
+                    .filter(|test| filters.is_empty() || filters.iter().any(|f| test.contains(f)))
+                    .collect();
+                if !tests.is_empty() {
+                    run(crate_dir, &tests, cargo_args)?;
+                }
+            }
+        }
+        before = scan(crate_dir)?;
+    }
+}
+
+fn run(crate_dir: &Path, filters: &[String], cargo_args: &[String]) -> io::Result<()> {
+    term::bold_color(Yellow);
+    print!("\nwatch");
+    term::reset();
+    if filters.is_empty() {
+        println!(": running all tests");
+    } else {
+        println!(": running {}", filters.join(", "));
+    }
+
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    let mut cmd = Command::new(cargo);
+    cmd.current_dir(crate_dir).arg("test").args(cargo_args);
+    if filters.is_empty() {
+        cmd.env_remove("TRYBUILD_FILTER");
+    } else {
+        let filter = env::join_paths(filters)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        cmd.env("TRYBUILD_FILTER", filter);
+    }
+    let status = cmd.status()?;
+
+    if status.success() {
+        term::bold_color(Green);
+        print!("watch");
+        term::reset();
+        println!(": ok, waiting for changes");
+    } else {
+        term::bold_color(Red);
+        print!("watch");
+        term::reset();
+        println!(": failed, waiting for changes");
+    }
+    Ok(())
+}
+
+fn scan(crate_dir: &Path) -> io::Result<Snapshot> {
+    let mut snapshot = Snapshot::new();
+    for file in &["Cargo.toml", "build.rs"] {
+        let path = crate_dir.join(file);
+        if let Ok(metadata) = fs::metadata(&path) {
+            snapshot.insert(path, metadata.modified().ok());
+        }
+    }
+    for dir in &["src", "tests"] {
+        scan_dir(&crate_dir.join(dir), &mut snapshot)?;
+    }
+    Ok(snapshot)
+}
+
+fn scan_dir(dir: &Path, snapshot: &mut Snapshot) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            scan_dir(&entry.path(), snapshot)?;
+        } else if file_type.is_file() {
+            let modified = entry.metadata()?.modified().ok();
+            snapshot.insert(entry.path(), modified);
+        }
+    }
+    Ok(())
+}
+
+fn changed_paths(before: &Snapshot, after: &Snapshot) -> Vec<PathBuf> {
+    let mut changed = Vec::new();
+    for (path, modified) in after {
+        if before.get(path) != Some(modified) {
+            changed.push(path.clone());
+        }
+    }
+    for path in before.keys() {
+        if !after.contains_key(path) {
+            changed.push(path.clone());
+        }
+    }
+    changed
+}
+
+#[derive(PartialEq, Debug)]
+enum Affected {
+    All,
+    // Paths of test cases relative to the crate directory, in the form they
+    // are matched against by TRYBUILD_FILTER.
+    Tests(Vec<String>),
+}
+
+fn affected(crate_dir: &Path, changed: &[PathBuf]) -> Affected {
+    let tests_dir = crate_dir.join("tests");
+    let mut tests = Vec::new();
+    for path in changed {
+        let relative = match path.strip_prefix(&tests_dir) {
+            Ok(relative) if relative.components().count() > 1 => relative,
+            _ => return Affected::All,
+        };
+        // Anything within a test case that is a crate of its own belongs to
+        // the directory with its Cargo.toml, and the modules of tests/ui/foo.rs
+        // in tests/ui/foo/ belong to tests/ui/foo.rs if it asks for them.
+        let owner = relative
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.parent().is_some())
+            .filter_map(|dir| {
+                if tests_dir.join(dir).join("Cargo.toml").exists() {
+                    Some(dir.to_owned())
+                } else if dir.components().count() > 1
+                    && directives::parse(&tests_dir.join(dir).with_extension("rs")).modules
+                {
+                    Some(dir.with_extension("rs"))
+                } else {
+                    None
+                }
+            })
+            .last();
+        let aux_dir = relative
+            .parent()
+            .filter(|dir| dir.file_name().map_or(false, |name| name == "aux"));
+        let test = match (owner, aux_dir) {
+            (Some(owner), _) => owner,
+            // Helper crates are shared by the test cases of their directory.
+            (None, Some(aux_dir)) => aux_dir.with_file_name(""),
+            (None, None) => {
+                let source = crate::cli::test_source(relative);
+                let dir = source.with_extension("");
+                if tests_dir.join(&dir).join("Cargo.toml").exists() {
+                    dir
+                } else {
+                    source
+                }
+            }
+        };
+        let test = Path::new("tests").join(test);
+        let test = test.to_string_lossy().replace('\\', "/");
+        if !tests.contains(&test) {
+            tests.push(test);
+        }
+    }
+    Affected::Tests(tests)
+}
+
+#[test]
+fn test_affected() {
+    let crate_dir = Path::new("/krate");
+    let paths =
+        |paths: &[&str]| -> Vec<PathBuf> { paths.iter().map(|p| crate_dir.join(p)).collect() };
+
+    assert_eq!(
+        affected(
+            crate_dir,
+            &paths(&[
+                "tests/ui/a.rs",
+                "tests/ui/a.stderr",
+                "tests/ui/b.alt1.stderr"
+            ])
+        ),
+        Affected::Tests(vec!["tests/ui/a.rs".to_owned(), "tests/ui/b.rs".to_owned()]),
+    );
+    assert_eq!(
+        affected(crate_dir, &paths(&["tests/ui/a.rs", "src/lib.rs"])),
+        Affected::All,
+    );
+    assert_eq!(affected(crate_dir, &paths(&["tests/ui.rs"])), Affected::All);
+    assert_eq!(
+        affected(crate_dir, &paths(&["tests/ui/aux/helper.rs"])),
+        Affected::Tests(vec!["tests/ui/".to_owned()]),
+    );
+}
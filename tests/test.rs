@@ -18,4 +18,60 @@ fn test() {
     t.pass("tests/ui/run-fail.rs");
     t.pass("tests/ui/run-pass-9.rs");
     t.compile_fail("tests/ui/compile-fail-2.rs");
+    t.pass("tests/ui/modules.rs");
+    t.compile_fail("tests/ui/aux-build.rs");
+    t.compile_fail("tests/ui/proc-macro.rs");
+    t.doc_examples("tests/ui/examples.md", "tests/ui/examples");
+    t.pass("tests/ui/crate-0");
+    t.compile_fail("tests/ui/crate-1");
+
+    let report = t.try_run().unwrap();
+    assert_eq!(report.tests(), 24);
+    assert_statuses(
+        &report,
+        &[
+            "tests/ui/run-pass-3.rs",
+            "tests/ui/compile-fail-0.rs",
+            "tests/ui/run-fail.rs",
+            "tests/ui/compile-fail-2.rs",
+        ],
+    );
+}
+
+#[test]
+fn lints() {
+    let t = trybuild::TestCases::new();
+    t.lint("unused_must_use", "deny");
+    t.compile_fail("tests/ui/lints.rs");
+    assert_statuses(&t.try_run().unwrap(), &[]);
+}
+
+#[cfg(unix)]
+#[test]
+fn run_wrapper() {
+    let t = trybuild::TestCases::new();
+    t.run_wrapper(vec!["env", "WRAPPED=1"]);
+    t.pass("tests/ui/run-wrapper.rs");
+    assert_statuses(&t.try_run().unwrap(), &[]);
+}
+
+#[test]
+fn update_directive() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/compile-fail-1.rs");
+    let report = t.verify().unwrap();
+    assert_eq!(report.outcomes()[0].status(), trybuild::Status::Passed);
+}
+
+// Every test case passes except for the intentionally broken ones.
+fn assert_statuses(report: &trybuild::Report, failed: &[&str]) {
+    for outcome in report.outcomes() {
+        let path = outcome.path().to_string_lossy().replace('\\', "/");
+        let expected = if failed.contains(&path.as_str()) {
+            trybuild::Status::Failed
+        } else {
+            trybuild::Status::Passed
+        };
+        assert_eq!(outcome.status(), expected, "{}", path);
+    }
 }
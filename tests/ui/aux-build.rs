@@ -0,0 +1,5 @@
+// trybuild-aux-build: foreign.rs
+
+impl foreign::Trait for foreign::Type {}
+
+fn main() {}
@@ -1,3 +1,5 @@
+// trybuild-update: wip
+
 compile_error!("ERROR");
 
 fn main() {}
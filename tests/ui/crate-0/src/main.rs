@@ -0,0 +1,3 @@
+fn main() {
+    assert_eq!(env!("FROM_BUILD_SCRIPT"), "ok");
+}
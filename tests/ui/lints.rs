@@ -0,0 +1,8 @@
+#[must_use]
+fn answer() -> u32 {
+    42
+}
+
+fn main() {
+    answer();
+}
@@ -0,0 +1,7 @@
+// trybuild-modules
+
+mod helper;
+
+fn main() {
+    assert_eq!(helper::answer(), 42);
+}
@@ -0,0 +1,12 @@
+// trybuild-crate-type: proc-macro
+
+use proc_macro::TokenStream;
+
+#[proc_macro]
+pub fn noop(input: TokenStream) -> TokenStream {
+    input
+}
+
+fn main() {
+    noop!();
+}
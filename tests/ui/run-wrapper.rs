@@ -0,0 +1,3 @@
+fn main() {
+    assert_eq!(std::env::var("WRAPPED").as_deref(), Ok("1"));
+}